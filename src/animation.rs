@@ -0,0 +1,384 @@
+use std::f64::consts::{LN_10, PI, TAU};
+use std::io;
+use std::path::Path;
+use bigdecimal::{BigDecimal, RoundingMode};
+use crate::bigcoord::{self, BigComplex};
+use crate::export;
+use crate::mandelbrot::{Complex64, FractalType, State};
+use crate::palette;
+use crate::params;
+use crate::supersample::{self, Supersampling};
+
+pub const DEFAULT_WIDTH: u32 = 1280;
+pub const DEFAULT_HEIGHT: u32 = 720;
+const DEFAULT_KEY_FRAMES: u32 = 60;
+
+/// A sequence of views that can be rendered to numbered frames.
+pub trait Animation {
+    fn frame_count(&self) -> u32;
+
+    /// The view and palette of frame i, counting from 0.
+    fn frame(&self, i: u32) -> (State, palette::Palette);
+}
+
+/// How far through an animation of frames frames frame i is, from 0 to 1.
+pub fn progress(i: u32, frames: u32) -> f64 {
+    if frames <= 1 {
+        return 1.0;
+    }
+    i as f64 / (frames - 1) as f64
+}
+
+/// The center and scale a fraction t of the way through an exponential zoom
+/// from (c0, s0) to (c1, s1).
+///
+/// The scale changes by the same factor every step, and the center is moved
+/// in proportion to the change in scale, so the point being zoomed into
+/// travels in a straight line across the screen instead of drifting.
+///
+/// The scales are interpolated through their logarithms, so zooms can go
+/// deeper than f64 can reach.
+pub fn interpolate_view(c0: &BigComplex, s0: &BigDecimal, c1: &BigComplex, s1: &BigDecimal, t: f64) -> (BigComplex, BigDecimal) {
+    let (l0, l1) = (bigcoord::log10(s0), bigcoord::log10(s1));
+    let l = l0 + (l1 - l0) * t;
+    // the weight of c0 is (scale - s1) / (s0 - s1), written in terms of
+    // powers of 10 that cannot overflow
+    let (d, u) = (l0 - l1, l - l1);
+    let (weight, from, to) = if d.abs() <= f64::EPSILON * l0.abs().max(l1.abs()).max(1.0) {
+        (t, c0, c1)
+    } else if d > 0.0 {
+        (10f64.powf(u - d) * (-u * LN_10).exp_m1() / (-d * LN_10).exp_m1(), c1, c0)
+    } else {
+        ((u * LN_10).exp_m1() / (d * LN_10).exp_m1(), c1, c0)
+    };
+    // keep digits well past the size of a pixel of any render size
+    let places = (-l).ceil() as i64 + 12;
+    let weight = bigcoord::from_f64(weight);
+    let lerp = |a: &BigDecimal, b: &BigDecimal| {
+        (a + (b - a) * &weight).with_scale_round(places, RoundingMode::HalfEven).normalized()
+    };
+    let center = BigComplex {
+        re: lerp(&from.re, &to.re),
+        im: lerp(&from.im, &to.im),
+    };
+    (center, bigcoord::pow10(l))
+}
+
+/// An exponentially interpolated zoom from one view to another.
+pub struct ZoomAnimation {
+    pub start: State,
+    pub end: State,
+    pub frames: u32,
+    pub palette: palette::PaletteType,
+}
+
+impl Animation for ZoomAnimation {
+    fn frame_count(&self) -> u32 {
+        self.frames
+    }
+
+    fn frame(&self, i: u32) -> (State, palette::Palette) {
+        let t = progress(i, self.frames);
+        let mut state = self.start.clone();
+        (state.center, state.scale) = interpolate_view(&self.start.center, &self.start.scale, &self.end.center, &self.end.scale, t);
+        // ramp the iterations so deep frames get enough detail without
+        // slowing down the shallow ones
+        let (i0, i1) = (self.start.max_iterations as f64, self.end.max_iterations as f64);
+        state.max_iterations = (i0 + (i1 - i0) * t).round() as u32;
+        (state, self.palette.to_palette())
+    }
+}
+
+/// Render every frame of an animation to frame_NNNNN.png files in dir,
+/// calling on_frame with the number of frames written so far.
+pub fn render_frames(
+    animation: &dyn Animation,
+    dir: &Path,
+    supersampling: &Supersampling,
+    on_frame: &mut dyn FnMut(u32),
+) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for i in 0..animation.frame_count() {
+        let (state, pal) = animation.frame(i);
+        let image = supersample::render_state(state, supersampling, &pal);
+        export::save_png(&export::frame_path(dir, i), &image)?;
+        on_frame(i + 1);
+    }
+    Ok(())
+}
+
+/// How the interpolation between two keyframes speeds up and slows down.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub const ALL: [Easing; 4] = [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut];
+
+    /// Map linear progress t, from 0 to 1, through the easing curve.
+    pub fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Easing::Linear => "linear",
+            Easing::EaseIn => "ease_in",
+            Easing::EaseOut => "ease_out",
+            Easing::EaseInOut => "ease_in_out",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Easing> {
+        Easing::ALL.into_iter().find(|e| e.name() == name)
+    }
+}
+
+/// A point on an animation timeline.
+#[derive(Clone)]
+pub struct Keyframe {
+    pub state: State,
+    pub palette: palette::PaletteType,
+    pub palette_offset: f64,
+    /// The easing used on the way to the next keyframe.
+    pub easing: Easing,
+    /// The number of frames between this keyframe and the next.
+    pub frames: u32,
+}
+
+/// Interpolate every part of a view between keyframes, following the
+/// easing curve of each keyframe.
+pub struct KeyframeAnimation {
+    pub keys: Vec<Keyframe>,
+}
+
+impl KeyframeAnimation {
+    /// Read keyframes from a parameter file with a `[keyframe]` section per
+    /// key. Values not given in a section carry over from the keyframe
+    /// before it.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut keys: Vec<Keyframe> = Vec::new();
+        for section in params::parse(text)?.into_iter().filter(|s| s.name == "keyframe") {
+            let mut key = match keys.last() {
+                Some(key) => key.clone(),
+                None => Keyframe {
+                    state: State::new(DEFAULT_WIDTH, DEFAULT_HEIGHT).home(),
+                    palette: palette::PaletteType::Color1Lin,
+                    palette_offset: 0.0,
+                    easing: Easing::EaseInOut,
+                    frames: DEFAULT_KEY_FRAMES,
+                },
+            };
+            for (name, value) in section.values.iter() {
+                if params::apply_state_value(&mut key.state, name, value)? {
+                    continue;
+                }
+                let bad = || format!("bad value for {}: `{}`", name, value);
+                match name.as_str() {
+                    "palette" => key.palette = palette::PaletteType::from_name(value).ok_or_else(bad)?,
+                    "palette_offset" => key.palette_offset = value.parse().map_err(|_| bad())?,
+                    "easing" => key.easing = Easing::from_name(value).ok_or_else(bad)?,
+                    "frames" => key.frames = value.parse().map_err(|_| bad())?,
+                    _ => return Err(format!("unknown keyframe value `{}`", name)),
+                }
+            }
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Err(String::from("no [keyframe] sections found"));
+        }
+        Ok(Self { keys })
+    }
+
+    /// Write the keyframes in the form parse reads.
+    pub fn to_params(&self) -> String {
+        let mut out = String::new();
+        for key in self.keys.iter() {
+            out.push_str("[keyframe]\n");
+            params::write_state(&mut out, &key.state);
+            out.push_str(&format!("palette = {:?}\n", key.palette));
+            out.push_str(&format!("palette_offset = {}\n", key.palette_offset));
+            out.push_str(&format!("easing = {}\n", key.easing.name()));
+            out.push_str(&format!("frames = {}\n\n", key.frames));
+        }
+        out
+    }
+
+    /// The keyframes on either side of frame i and the eased progress
+    /// between them.
+    fn segment(&self, i: u32) -> (&Keyframe, &Keyframe, f64) {
+        let mut start = 0;
+        for pair in self.keys.windows(2) {
+            let frames = pair[0].frames.max(1);
+            if i < start + frames {
+                let t = (i - start) as f64 / frames as f64;
+                return (&pair[0], &pair[1], pair[0].easing.apply(t));
+            }
+            start += frames;
+        }
+        let last = self.keys.last().unwrap();
+        (last, last, 0.0)
+    }
+}
+
+impl Animation for KeyframeAnimation {
+    fn frame_count(&self) -> u32 {
+        let between: u32 = self.keys[..self.keys.len() - 1].iter().map(|k| k.frames.max(1)).sum();
+        between + 1
+    }
+
+    fn frame(&self, i: u32) -> (State, palette::Palette) {
+        let (a, b, t) = self.segment(i);
+        let lerp = |x: f64, y: f64| x + (y - x) * t;
+        let first = &self.keys[0].state;
+        let mut state = a.state.clone();
+        (state.width, state.height) = (first.width, first.height);
+        (state.center, state.scale) = interpolate_view(&a.state.center, &a.state.scale, &b.state.center, &b.state.scale, t);
+        state.rotation = lerp_angle(a.state.rotation, b.state.rotation, t);
+        state.max_iterations = lerp(a.state.max_iterations as f64, b.state.max_iterations as f64).round() as u32;
+        if let (FractalType::Julia(c0), FractalType::Julia(c1)) = (&a.state.fractal_type, &b.state.fractal_type) {
+            state.fractal_type = FractalType::Julia(c0 + (c1 - c0) * t);
+        }
+        let offset = lerp(a.palette_offset, b.palette_offset).round().max(0.0) as usize;
+        (state, a.palette.to_palette_with_offset(offset))
+    }
+}
+
+/// The angle a fraction t of the way from a to b, in radians, turning the
+/// shorter way round.
+fn lerp_angle(a: f64, b: f64, t: f64) -> f64 {
+    let turn = (b - a + PI).rem_euclid(TAU) - PI;
+    a + turn * t
+}
+
+/// The point a fraction t of the way along path, measured by length. A
+/// closed path returns to its first point at t = 1.
+pub fn point_along(path: &[Complex64], closed: bool, t: f64) -> Complex64 {
+    let mut segments: Vec<(Complex64, Complex64)> = path.windows(2).map(|p| (p[0], p[1])).collect();
+    if closed && path.len() > 2 {
+        segments.push((path[path.len() - 1], path[0]));
+    }
+    let total: f64 = segments.iter().map(|(a, b)| (b - a).norm()).sum();
+    let mut remaining = t.clamp(0.0, 1.0) * total;
+    for (a, b) in segments.iter() {
+        let length = (b - a).norm();
+        if remaining <= length && length > 0.0 {
+            return a + (b - a) * (remaining / length);
+        }
+        remaining -= length;
+    }
+    match segments.last() {
+        Some((_, b)) if !closed => *b,
+        _ => path[0],
+    }
+}
+
+/// A closed path of points around a circle, such as around a bulb of the
+/// Mandelbrot set.
+pub fn circle_path(center: Complex64, radius: f64, points: u32) -> Vec<Complex64> {
+    (0..points)
+        .map(|i| center + Complex64::from_polar(radius, std::f64::consts::TAU * i as f64 / points as f64))
+        .collect()
+}
+
+/// The Julia set of a constant moving along a path, usually one drawn over
+/// the Mandelbrot set.
+pub struct JuliaMorph {
+    /// The view of every frame, its fractal type is replaced.
+    pub view: State,
+    pub path: Vec<Complex64>,
+    pub closed: bool,
+    pub frames: u32,
+    pub palette: palette::PaletteType,
+}
+
+impl JuliaMorph {
+    /// The Julia constant of frame i.
+    pub fn constant(&self, i: u32) -> Complex64 {
+        // a loop ends one step before it is back at the start, so the frames
+        // can be played on repeat without a stutter
+        let t = match self.closed {
+            true => i as f64 / self.frames.max(1) as f64,
+            false => progress(i, self.frames),
+        };
+        point_along(&self.path, self.closed, t)
+    }
+}
+
+impl Animation for JuliaMorph {
+    fn frame_count(&self) -> u32 {
+        self.frames
+    }
+
+    fn frame(&self, i: u32) -> (State, palette::Palette) {
+        let mut state = self.view.clone();
+        state.fractal_type = FractalType::Julia(self.constant(i));
+        (state, self.palette.to_palette())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyframes_turn_the_short_way_round() {
+        let text = "[keyframe]\nrotation = 170\neasing = linear\nframes = 2\n[keyframe]\nrotation = -170\n";
+        let animation = KeyframeAnimation::parse(text).unwrap();
+        let degrees: Vec<f64> = (0..3).map(|i| animation.frame(i).0.rotation.to_degrees()).collect();
+        for (got, expected) in degrees.iter().zip([170.0, 180.0, -170.0]) {
+            assert!((got - expected).abs() < 1e-9, "{:?}", degrees);
+        }
+        assert!((lerp_angle(0.5, 1.5, 0.5) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn interpolated_views_zoom_towards_a_fixed_point() {
+        let c0 = BigComplex::parse("-0.5, 0").unwrap();
+        let c1 = BigComplex::parse("-0.743643887037158704752191506114774, 0.131825904205311970493132056385139").unwrap();
+        let to = c0.difference(&c1);
+        for (s0, s1) in [("4", "0.001"), ("4", "1e-300"), ("1e-300", "4")] {
+            let (s0, s1): (BigDecimal, BigDecimal) = (s0.parse().unwrap(), s1.parse().unwrap());
+            let (start, end) = (interpolate_view(&c0, &s0, &c1, &s1, 0.0), interpolate_view(&c0, &s0, &c1, &s1, 1.0));
+            assert!(start.0.difference(&c0).norm() < 1e-12 && end.0.difference(&c1).norm() < 1e-12 * bigcoord::to_f64(&s1));
+            for t in [0.1, 0.5, 0.9] {
+                let (center, scale) = interpolate_view(&c0, &s0, &c1, &s1, t);
+                let (l0, l1) = (bigcoord::log10(&s0), bigcoord::log10(&s1));
+                assert!((bigcoord::log10(&scale) - (l0 + (l1 - l0) * t)).abs() < 1e-9);
+                // c1 stays where it is on the screen the whole way
+                let (s, s0, s1) = (bigcoord::to_f64(&scale), bigcoord::to_f64(&s0), bigcoord::to_f64(&s1));
+                let expected = to * ((s - s1) / (s0 - s1));
+                assert!((center.difference(&c1) - expected).norm() < 1e-9 * s, "t = {}", t);
+            }
+        }
+    }
+
+    #[test]
+    fn points_along_a_path_are_spaced_by_length() {
+        let c = |re, im| Complex64::new(re, im);
+        // an L of lengths 3 and 1, closed by a diagonal of length sqrt(10)
+        let path = [c(0.0, 0.0), c(3.0, 0.0), c(3.0, 1.0)];
+        let close = |a: Complex64, b: Complex64| (a - b).norm() < 1e-12;
+        assert!(close(point_along(&path, false, 0.0), c(0.0, 0.0)));
+        assert!(close(point_along(&path, false, 0.5), c(2.0, 0.0)));
+        assert!(close(point_along(&path, false, 0.875), c(3.0, 0.5)));
+        assert!(close(point_along(&path, false, 1.0), c(3.0, 1.0)));
+        assert!(close(point_along(&path, false, 2.0), c(3.0, 1.0)));
+        let total = 4.0 + 10f64.sqrt();
+        assert!(close(point_along(&path, true, 3.5 / total), c(3.0, 0.5)));
+        assert!(close(point_along(&path, true, (4.0 + 10f64.sqrt() / 2.0) / total), c(1.5, 0.5)));
+        assert!(close(point_along(&path, true, 1.0), c(0.0, 0.0)));
+        // repeated points are skipped rather than divided by
+        assert!(close(point_along(&[c(1.0, 1.0), c(1.0, 1.0), c(2.0, 1.0)], false, 0.5), c(1.5, 1.0)));
+        assert!(close(point_along(&[c(1.0, 1.0)], true, 0.5), c(1.0, 1.0)));
+    }
+}
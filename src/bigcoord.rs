@@ -0,0 +1,138 @@
+use std::fmt::Display;
+use std::str::FromStr;
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::{BigDecimal, FromPrimitive, RoundingMode, ToPrimitive};
+use crate::mandelbrot::Complex64;
+
+/// The number of significant digits kept when the scale is multiplied, far
+/// more than any render can use.
+const SCALE_DIGITS: u64 = 20;
+/// The number of decimal places kept in points beyond the size of a pixel.
+const EXTRA_PLACES: i64 = 6;
+
+/// The shortest decimal that converts back to value. Infinities and NaN
+/// become zero.
+pub fn from_f64(value: f64) -> BigDecimal {
+    if !value.is_finite() {
+        return BigDecimal::default();
+    }
+    BigDecimal::from_str(&format!("{:e}", value)).unwrap()
+}
+
+/// The nearest f64 to value, which underflows to zero and overflows to
+/// infinity.
+pub fn to_f64(value: &BigDecimal) -> f64 {
+    value.to_f64().unwrap_or(0.0)
+}
+
+/// The base 10 logarithm of a positive value, which may be far outside the
+/// range of f64.
+pub fn log10(value: &BigDecimal) -> f64 {
+    let (digits, exponent) = value.with_prec(17).as_bigint_and_exponent();
+    digits.to_f64().unwrap_or(0.0).abs().log10() - exponent as f64
+}
+
+/// 10 to the power of exponent, the inverse of log10.
+pub fn pow10(exponent: f64) -> BigDecimal {
+    let whole = exponent.floor();
+    let mantissa = 10f64.powf(exponent - whole);
+    // powf is good to about 15 digits, rounding there turns results such as
+    // 1.9999999999999998 back into 2
+    let digits = BigInt::from_f64((mantissa * 1e14).round()).unwrap_or_default();
+    BigDecimal::new(digits, 14 - whole as i64).normalized()
+}
+
+/// The number of decimal places needed for points of a view with pixels of
+/// the given size. Sizes of zero or beyond f64 give just EXTRA_PLACES.
+pub fn places(pixel: f64) -> i64 {
+    let needed = (-pixel.abs().log10()).ceil();
+    let needed = if needed.is_finite() { needed as i64 } else { 0 };
+    needed.max(0).saturating_add(EXTRA_PLACES)
+}
+
+/// Multiply a scale by an f64 factor, keeping SCALE_DIGITS digits.
+pub fn scale_by(scale: &BigDecimal, factor: f64) -> BigDecimal {
+    (scale * from_f64(factor)).with_prec(SCALE_DIGITS).normalized()
+}
+
+/// value as an unevaluated sum of four f64s, the largest first, for the
+/// extended precision types.
+pub fn expansion(value: &BigDecimal) -> [f64; 4] {
+    let mut parts = [0.0; 4];
+    let mut rest = value.clone();
+    for part in parts.iter_mut() {
+        *part = to_f64(&rest);
+        if *part == 0.0 || !part.is_finite() {
+            break;
+        }
+        // an f64 is exactly representable as a decimal
+        rest -= BigDecimal::from_f64(*part).unwrap();
+    }
+    parts
+}
+
+/// A point of the complex plane with arbitrary precision parts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BigComplex {
+    pub re: BigDecimal,
+    pub im: BigDecimal,
+}
+
+impl BigComplex {
+    pub fn to_complex(&self) -> Complex64 {
+        Complex64::new(to_f64(&self.re), to_f64(&self.im))
+    }
+
+    /// This point moved by offset, rounded to places decimal places.
+    pub fn offset(&self, offset: Complex64, places: i64) -> Self {
+        let round = |x: BigDecimal| x.with_scale_round(places, RoundingMode::HalfEven).normalized();
+        Self {
+            re: round(&self.re + from_f64(offset.re)),
+            im: round(&self.im + from_f64(offset.im)),
+        }
+    }
+
+    /// self - other, rounded to f64 after subtracting so nearby points stay
+    /// apart.
+    pub fn difference(&self, other: &BigComplex) -> Complex64 {
+        Complex64::new(to_f64(&(&self.re - &other.re)), to_f64(&(&self.im - &other.im)))
+    }
+
+    /// The real and imaginary parts as expansions.
+    pub fn expansion(&self) -> ([f64; 4], [f64; 4]) {
+        (expansion(&self.re), expansion(&self.im))
+    }
+
+    /// Parse a point written as `re, im`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (re, im) = value.split_once(',')?;
+        Some(Self {
+            re: BigDecimal::from_str(re.trim()).ok()?,
+            im: BigDecimal::from_str(im.trim()).ok()?,
+        })
+    }
+}
+
+impl Display for BigComplex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}, {}", self.re, self.im)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn places_follow_the_pixel_size() {
+        assert_eq!(places(1e-3), 3 + EXTRA_PLACES);
+        assert_eq!(places(10.0), EXTRA_PLACES);
+    }
+
+    #[test]
+    fn places_of_degenerate_pixels_do_not_overflow() {
+        for pixel in [0.0, f64::NAN, f64::INFINITY, 1e-320] {
+            assert!(places(pixel) >= EXTRA_PLACES);
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use bigdecimal::BigDecimal;
+use crate::animation::{circle_path, render_frames, Animation, JuliaMorph, KeyframeAnimation, ZoomAnimation, DEFAULT_HEIGHT, DEFAULT_WIDTH};
+use crate::bigcoord::BigComplex;
+use crate::export;
+use crate::expmap::ExpMap;
+use crate::mandelbrot::{Complex64, FractalType, Precision, State, MIN_SCALE};
+use crate::palette::PaletteType;
+use crate::progress::{format_duration, Progress};
+use crate::params;
+use crate::supersample::Supersampling;
+
+const USAGE: &str = "usage: fractal_rs [command] [--option value]...
+
+With no command the interactive viewer is started.
+
+commands:
+  zoom      render an exponential zoom to a numbered PNG sequence
+            --center RE,IM --scale S      the view the zoom ends on
+            --start-center RE,IM          default: the whole set
+            --start-scale S
+            --frames N                    default: 100
+            --out DIR                     default: zoom_NNNN
+  keyframes render a keyframe file to a numbered PNG sequence
+            --file FILE                   one [keyframe] section per key
+            --out DIR                     default: keyframes_NNNN
+            --size WxH                    overrides the size in the file
+  expmap    render a zoom into a point as log polar strips, one per octave,
+            from which expmap-frames builds the frames much faster than
+            rendering each one
+            --center RE,IM --scale S      the view the zoom ends on
+            --start-scale S               default: the whole set
+            --out DIR                     default: expmap_NNNN
+            every strip uses the end of an --iterations ramp
+  expmap-frames
+            build the frames of a zoom from the strips of expmap
+            --strips DIR                  the output directory of expmap
+            --frames N                    default: 100
+            --out DIR                     default: zoom_NNNN
+  export    render a single image of any size to a PNG file, a band of
+            rows at a time so memory use stays fixed
+            --center RE,IM --scale S      default: the whole set
+            --out FILE                    default: fractal_NNNN.png
+            --band-rows N                 default: about 4 million pixels
+  julia-morph
+            render the Julia set of a constant moving along a path
+            --around RE,IM --radius R     loop around a circle
+            --path 'RE,IM;RE,IM;...'      or follow these points
+            --frames N                    default: 100
+            --out DIR                     default: morph_NNNN
+  help      show this message
+
+options shared by all commands:
+  --size WxH            default: 1280x720
+  --iterations N|A:B    the iteration limit, or a ramp from A to B across
+                        an animation, default: 500
+  --palette NAME        bw, color1lin, color1mod, color2lin or color2mod
+  --julia RE,IM         render the Julia set for this constant
+  --precision NAME      auto, f32, f64, double-double or quad-double,
+                        default: auto, which picks by the zoom level
+  --supersample SPEC    average several samples per pixel: N for an NxN
+                        grid, jitter:N for random points in the grid, and
+                        either after adaptive: to only supersample edges,
+                        such as adaptive:jitter:3, default: off";
+
+const DEFAULT_FRAMES: u32 = 100;
+/// How often progress lines are updated.
+const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Run the command line interface with the program arguments, excluding the
+/// program name.
+pub fn run(args: &[String]) -> Result<(), String> {
+    let options = Options::parse(&args[1..])?;
+    match args[0].as_str() {
+        "zoom" => zoom(&options),
+        "keyframes" => keyframes(&options),
+        "expmap" => expmap(&options),
+        "export" => export_image(&options),
+        "julia-morph" => julia_morph(&options),
+        "expmap-frames" => expmap_frames(&options),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(format!("unknown command `{}`\n\n{}", other, USAGE)),
+    }
+}
+
+/// `--name value` pairs from the command line.
+struct Options {
+    values: Vec<(String, String)>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut values = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or_else(|| format!("expected an option, found `{}`", arg))?;
+            let value = args.next().ok_or_else(|| format!("missing value for --{}", name))?;
+            values.push((name.to_string(), value.clone()));
+        }
+        Ok(Self { values })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.values.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    fn require(&self, name: &str) -> Result<&str, String> {
+        self.get(name).ok_or_else(|| format!("--{} is required", name))
+    }
+
+    fn parse_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.get(name) {
+            Some(value) => value.parse().map_err(|_| format!("bad value for --{}: `{}`", name, value)),
+            None => Ok(default),
+        }
+    }
+
+    fn complex(&self, name: &str) -> Result<Option<Complex64>, String> {
+        self.get(name).map(|v| parse_complex(name, v)).transpose()
+    }
+
+    fn center(&self, name: &str) -> Result<Option<BigComplex>, String> {
+        self.get(name).map(|v| parse_center(name, v)).transpose()
+    }
+
+    fn scale_or(&self, name: &str, default: BigDecimal) -> Result<BigDecimal, String> {
+        match self.get(name) {
+            Some(value) => parse_scale(name, value),
+            None => Ok(default),
+        }
+    }
+}
+
+fn parse_complex(name: &str, value: &str) -> Result<Complex64, String> {
+    params::parse_complex(value).ok_or_else(|| format!("bad value for --{}: expected RE,IM, found `{}`", name, value))
+}
+
+/// parse_complex for a point kept with arbitrary precision.
+fn parse_center(name: &str, value: &str) -> Result<BigComplex, String> {
+    BigComplex::parse(value).ok_or_else(|| format!("bad value for --{}: expected RE,IM, found `{}`", name, value))
+}
+
+fn parse_scale(name: &str, value: &str) -> Result<BigDecimal, String> {
+    params::parse_scale(value).ok_or_else(|| format!("bad value for --{}: expected a positive number no smaller than {:e}, found `{}`", name, MIN_SCALE, value))
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    params::parse_size(value).ok_or_else(|| format!("bad value for --size: expected WxH, found `{}`", value))
+}
+
+/// The iteration limits at the start and end of an animation.
+fn parse_iterations(value: &str) -> Result<(u32, u32), String> {
+    let bad = || format!("bad value for --iterations: expected N or A:B, found `{}`", value);
+    match value.split_once(':') {
+        Some((a, b)) => Ok((a.parse().map_err(|_| bad())?, b.parse().map_err(|_| bad())?)),
+        None => {
+            let n = value.parse().map_err(|_| bad())?;
+            Ok((n, n))
+        }
+    }
+}
+
+/// The starting view described by the shared options.
+fn base_state(options: &Options) -> Result<State, String> {
+    let (width, height) = match options.get("size") {
+        Some(size) => parse_size(size)?,
+        None => (DEFAULT_WIDTH, DEFAULT_HEIGHT),
+    };
+    let mut state = State::new(width, height);
+    if let Some(c) = options.complex("julia")? {
+        state.fractal_type = FractalType::Julia(c);
+    }
+    let mut state = state.home();
+    state.max_iterations = iterations(options)?.0;
+    if let Some(name) = options.get("precision") {
+        state.precision = Precision::from_name(name).ok_or_else(|| format!("unknown precision `{}`", name))?;
+    }
+    Ok(state)
+}
+
+/// Warn when state is too deep for its precision to render anything but
+/// noise.
+fn warn_if_exhausted(state: &State) {
+    if state.precision_exhausted() {
+        eprintln!("warning: {} precision is exhausted at scale {}, the image will be noise", state.render_precision().name(), state.scale);
+    }
+}
+
+fn iterations(options: &Options) -> Result<(u32, u32), String> {
+    match options.get("iterations") {
+        Some(value) => parse_iterations(value),
+        None => {
+            let n = State::new(1, 1).max_iterations;
+            Ok((n, n))
+        }
+    }
+}
+
+fn palette(options: &Options) -> Result<PaletteType, String> {
+    match options.get("palette") {
+        Some(name) => PaletteType::from_name(name).ok_or_else(|| format!("unknown palette `{}`", name)),
+        None => Ok(PaletteType::Color1Lin),
+    }
+}
+
+fn supersampling(options: &Options) -> Result<Supersampling, String> {
+    match options.get("supersample") {
+        Some(spec) => Supersampling::parse(spec).ok_or_else(|| format!("bad value for --supersample: `{}`", spec)),
+        None => Ok(Supersampling::OFF),
+    }
+}
+
+fn out_dir(options: &Options, prefix: &str) -> PathBuf {
+    match options.get("out") {
+        Some(dir) => PathBuf::from(dir),
+        None => export::next_file_name(prefix, ""),
+    }
+}
+
+/// Sets a flag when dropped.
+struct StopOnDrop<'a>(&'a AtomicBool);
+
+impl Drop for StopOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Run job, printing how many of total it has done and the time left on a
+/// line that overwrites itself until it returns.
+fn with_progress<R>(what: &str, total: u64, job: impl FnOnce(&Progress) -> R) -> R {
+    let progress = Progress::new();
+    progress.start(total);
+    let finished = AtomicBool::new(false);
+    let result = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            while !finished.load(Ordering::Relaxed) {
+                // the padding covers the end of a longer previous line
+                print!("\r{} {}      ", what, progress.describe());
+                std::io::stdout().flush().ok();
+                std::thread::sleep(REPORT_INTERVAL);
+            }
+        });
+        // stops the reporter even if job panics, or the scope would wait
+        // for it forever
+        let _stop = StopOnDrop(&finished);
+        job(&progress)
+    });
+    let elapsed = format_duration(progress.elapsed());
+    println!("\r{} {}/{} in {}      ", what, progress.done(), progress.total(), elapsed);
+    result
+}
+
+fn zoom(options: &Options) -> Result<(), String> {
+    let mut start = base_state(options)?;
+    start.center = options.center("start-center")?.unwrap_or(start.center);
+    start.scale = options.scale_or("start-scale", start.scale)?;
+    let mut end = start.clone();
+    end.center = parse_center("center", options.require("center")?)?;
+    end.scale = parse_scale("scale", options.require("scale")?)?;
+    end.max_iterations = iterations(options)?.1;
+    warn_if_exhausted(&end);
+
+    let frames = options.parse_or("frames", DEFAULT_FRAMES)?;
+    let animation = ZoomAnimation {
+        start,
+        end,
+        frames,
+        palette: palette(options)?,
+    };
+    let dir = out_dir(options, "zoom");
+    println!("rendering {} frames to {}", frames, dir.display());
+    let supersampling = supersampling(options)?;
+    with_progress("frame", frames as u64, |progress| {
+        render_frames(&animation, &dir, &supersampling, &mut |done| progress.set(done as u64))
+    })
+    .map_err(|e| e.to_string())
+}
+
+fn keyframes(options: &Options) -> Result<(), String> {
+    let file = options.require("file")?;
+    let text = std::fs::read_to_string(file).map_err(|e| format!("failed to read {}: {}", file, e))?;
+    let mut animation = KeyframeAnimation::parse(&text).map_err(|e| format!("{}: {}", file, e))?;
+    if let Some(size) = options.get("size") {
+        let (width, height) = parse_size(size)?;
+        for key in animation.keys.iter_mut() {
+            (key.state.width, key.state.height) = (width, height);
+        }
+    }
+    animation.keys.iter().for_each(|key| warn_if_exhausted(&key.state));
+    let frames = animation.frame_count();
+    let dir = out_dir(options, "keyframes");
+    println!("rendering {} frames to {}", frames, dir.display());
+    let supersampling = supersampling(options)?;
+    with_progress("frame", frames as u64, |progress| {
+        render_frames(&animation, &dir, &supersampling, &mut |done| progress.set(done as u64))
+    })
+    .map_err(|e| e.to_string())
+}
+
+fn expmap(options: &Options) -> Result<(), String> {
+    let start = base_state(options)?;
+    let mut end = start.clone();
+    end.center = parse_center("center", options.require("center")?)?;
+    end.scale = parse_scale("scale", options.require("scale")?)?;
+    end.max_iterations = iterations(options)?.1;
+    warn_if_exhausted(&end);
+    let map = ExpMap {
+        end,
+        start_scale: options.scale_or("start-scale", start.scale.clone())?,
+        palette: palette(options)?,
+    };
+    let strips = map.strip_count();
+    let dir = out_dir(options, "expmap");
+    println!("rendering {} strips to {}", strips, dir.display());
+    with_progress("strip", strips as u64, |progress| {
+        map.render_strips(&dir, &mut |done| progress.set(done as u64))
+    })
+    .map_err(|e| e.to_string())
+}
+
+fn export_image(options: &Options) -> Result<(), String> {
+    let mut state = base_state(options)?;
+    state.center = options.center("center")?.unwrap_or(state.center);
+    state.scale = options.scale_or("scale", state.scale)?;
+    warn_if_exhausted(&state);
+    let rows = options.parse_or("band-rows", export::default_band_rows(state.width))?;
+    let path = match options.get("out") {
+        Some(file) => PathBuf::from(file),
+        None => export::next_file_name("fractal", "png"),
+    };
+    println!("rendering {}x{} to {}", state.width, state.height, path.display());
+    let pal = palette(options)?.to_palette();
+    let supersampling = supersampling(options)?;
+    with_progress("row", state.height as u64, |progress| {
+        export::save_png_bands(&path, &state, &pal, rows, &supersampling, progress)
+    })
+    .map_err(|e| format!("failed to write {}: {}", path.display(), e))
+}
+
+fn expmap_frames(options: &Options) -> Result<(), String> {
+    let strips = PathBuf::from(options.require("strips")?);
+    let map = ExpMap::load(&strips)?;
+    let frames = options.parse_or("frames", DEFAULT_FRAMES)?;
+    let dir = out_dir(options, "zoom");
+    println!("building {} frames in {}", frames, dir.display());
+    with_progress("frame", frames as u64, |progress| {
+        map.render_frames(&strips, frames, &dir, &mut |done| progress.set(done as u64))
+    })
+    .map_err(|e| e.to_string())
+}
+
+/// The number of points a circle is approximated by.
+const CIRCLE_POINTS: u32 = 256;
+
+fn julia_morph(options: &Options) -> Result<(), String> {
+    let (path, closed) = match (options.complex("around")?, options.get("path")) {
+        (Some(center), None) => {
+            let radius = options.require("radius")?.parse().map_err(|_| String::from("bad value for --radius"))?;
+            (circle_path(center, radius, CIRCLE_POINTS), true)
+        }
+        (None, Some(path)) => {
+            let points = path.split(';').map(|p| parse_complex("path", p)).collect::<Result<Vec<_>, _>>()?;
+            (points, false)
+        }
+        _ => return Err(String::from("one of --around or --path is required")),
+    };
+    let mut view = base_state(options)?;
+    view.fractal_type = FractalType::Julia(path[0]);
+    let max_iterations = view.max_iterations;
+    view = view.home();
+    view.max_iterations = max_iterations;
+
+    let frames = options.parse_or("frames", DEFAULT_FRAMES)?;
+    let animation = JuliaMorph {
+        view,
+        path,
+        closed,
+        frames,
+        palette: palette(options)?,
+    };
+    let dir = out_dir(options, "morph");
+    println!("rendering {} frames to {}", frames, dir.display());
+    let supersampling = supersampling(options)?;
+    with_progress("frame", frames as u64, |progress| {
+        render_frames(&animation, &dir, &supersampling, &mut |done| progress.set(done as u64))
+    })
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_progress_returns_when_the_job_panics() {
+        let result = std::panic::catch_unwind(|| with_progress("row", 1, |_| -> () { panic!("job failed") }));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use crate::mandelbrot::{compute_mandelbrot, Complex64, Data, FractalSample, State, KERNEL_VERSION};
+use crate::params;
+
+/// Environment variable overriding the location of the cache.
+pub const CACHE_DIR_ENV: &str = "FRACTAL_CACHE_DIR";
+/// Environment variable setting the size limit of the cache in megabytes, 0
+/// turns the cache off.
+pub const CACHE_SIZE_ENV: &str = "FRACTAL_CACHE_SIZE";
+const DEFAULT_SIZE_MB: u64 = 1024;
+/// Eviction removes the oldest files until the cache is this fraction of its
+/// limit, so it does not have to run again on the next store.
+const EVICT_TO: f64 = 0.9;
+const MAGIC: &[u8; 8] = b"FRACTAL1";
+/// The bytes stored per sample: the escape count and both parts of z.
+const SAMPLE_BYTES: u64 = 20;
+/// Temporary files older than this were left by a store that never finished,
+/// rather than one still being written by another process.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(3600);
+
+/// Computed escape data kept on disk between runs, one file per render.
+///
+/// Files are named by a hash of everything that affects the results, and
+/// hold the full description as well so a hash collision is not mistaken
+/// for a hit. Results of other kernel versions are deleted when the cache is
+/// opened.
+pub struct DiskCache {
+    dir: PathBuf,
+    limit: u64,
+    size: AtomicU64,
+    evicting: Mutex<()>,
+    /// Numbers the temporary files of stores running at the same time.
+    next_temp: AtomicU64,
+}
+
+/// The default location of the cache, under XDG_CACHE_HOME or ~/.cache.
+fn default_root() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os(CACHE_DIR_ENV) {
+        return Some(PathBuf::from(dir));
+    }
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache.join("fractal_rs"))
+}
+
+/// The cache files in dir with their sizes and modification times.
+fn files(dir: &Path) -> io::Result<Vec<(PathBuf, u64, SystemTime)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().map(|e| e == "bin").unwrap_or(false) {
+            let meta = entry.metadata()?;
+            files.push((path, meta.len(), meta.modified()?));
+        }
+    }
+    Ok(files)
+}
+
+/// Remove the temporary files of stores that were interrupted, which are
+/// not counted in the size of the cache and would never be evicted.
+fn remove_stale_temps(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_temp = path.extension().map(|e| e.to_string_lossy().starts_with("tmp")).unwrap_or(false);
+        let stale = entry
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .map(|modified| modified.elapsed().map(|age| age > STALE_TEMP_AGE).unwrap_or(false))
+            .unwrap_or(false);
+        if is_temp && stale {
+            fs::remove_file(&path).ok();
+        }
+    }
+}
+
+/// The 64 bit FNV-1a hash of bytes.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Everything about state that affects its results.
+fn key(state: &State) -> String {
+    let mut key = String::new();
+    params::write_state(&mut key, state);
+    writeln!(key, "render_precision = {}", state.render_precision().name()).unwrap();
+    key
+}
+
+fn write_data(path: &Path, key: &str, data: &Data) -> io::Result<u64> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&(key.len() as u32).to_le_bytes())?;
+    out.write_all(key.as_bytes())?;
+    out.write_all(&data.state.width.to_le_bytes())?;
+    out.write_all(&data.state.height.to_le_bytes())?;
+    for sample in data.samples() {
+        out.write_all(&sample.escape.to_le_bytes())?;
+        out.write_all(&sample.z.re.to_le_bytes())?;
+        out.write_all(&sample.z.im.to_le_bytes())?;
+    }
+    out.flush()?;
+    Ok(out.get_ref().metadata()?.len())
+}
+
+fn read_data(mut input: impl Read, key: &str, state: &State) -> io::Result<Data> {
+    let mut u32_buf = [0; 4];
+    let mut read_u32 = |input: &mut dyn Read| input.read_exact(&mut u32_buf).map(|_| u32::from_le_bytes(u32_buf));
+    let different = || io::Error::other("the file is for a different render");
+    let mut magic = [0; 8];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::other("not a cache file"));
+    }
+    // checked before reading, so a damaged length cannot ask for any amount
+    // of memory
+    if read_u32(&mut input)? as usize != key.len() {
+        return Err(different());
+    }
+    let mut stored_key = vec![0; key.len()];
+    input.read_exact(&mut stored_key)?;
+    let (width, height) = (read_u32(&mut input)?, read_u32(&mut input)?);
+    if stored_key != key.as_bytes() || (width, height) != (state.width, state.height) {
+        return Err(different());
+    }
+    let mut data = Data::new(state.clone());
+    let mut sample = [0; SAMPLE_BYTES as usize];
+    for entry in data.samples_mut() {
+        input.read_exact(&mut sample)?;
+        let f64_at = |i: usize| f64::from_le_bytes(sample[i..i + 8].try_into().unwrap());
+        *entry = FractalSample {
+            escape: u32::from_le_bytes(sample[..4].try_into().unwrap()),
+            z: Complex64::new(f64_at(4), f64_at(12)),
+        };
+    }
+    Ok(data)
+}
+
+impl DiskCache {
+    /// Open the cache in its default location with the configured size
+    /// limit. None when the cache is turned off or cannot be used.
+    pub fn open_default() -> Option<Self> {
+        let limit_mb = match std::env::var(CACHE_SIZE_ENV) {
+            Ok(value) => value.parse().unwrap_or_else(|_| {
+                println!("ignoring bad {}: `{}`", CACHE_SIZE_ENV, value);
+                DEFAULT_SIZE_MB
+            }),
+            Err(_) => DEFAULT_SIZE_MB,
+        };
+        if limit_mb == 0 {
+            return None;
+        }
+        let root = default_root()?;
+        match Self::open(&root, limit_mb * 1024 * 1024) {
+            Ok(cache) => Some(cache),
+            Err(err) => {
+                println!("not caching results in {}: {}", root.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Open the cache in root, holding at most limit bytes.
+    pub fn open(root: &Path, limit: u64) -> io::Result<Self> {
+        let dir = root.join(format!("kernel_{}", KERNEL_VERSION));
+        fs::create_dir_all(&dir)?;
+        // results of other kernel versions can never be used again
+        for entry in fs::read_dir(root)?.flatten() {
+            let stale = entry.file_name().to_string_lossy().starts_with("kernel_") && entry.path() != dir;
+            if stale && fs::remove_dir_all(entry.path()).is_ok() {
+                println!("removed stale cache {}", entry.path().display());
+            }
+        }
+        remove_stale_temps(&dir);
+        let size = files(&dir)?.iter().map(|(_, len, _)| len).sum();
+        let cache = Self {
+            dir,
+            limit,
+            size: AtomicU64::new(size),
+            evicting: Mutex::new(()),
+            next_temp: AtomicU64::new(0),
+        };
+        // the limit may have been lowered since the last run
+        if size > limit {
+            cache.evict();
+        }
+        Ok(cache)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.bin", fnv1a(key.as_bytes())))
+    }
+
+    /// The cached results of state, if there are any.
+    pub fn load(&self, state: &State) -> Option<Data> {
+        let key = key(state);
+        let path = self.path(&key);
+        let data = read_data(BufReader::new(File::open(&path).ok()?), &key, state).ok()?;
+        // eviction goes by modification time, so mark the file as used
+        File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+            .ok();
+        Some(data)
+    }
+
+    /// Cache the results in data, evicting the least recently used files
+    /// when the cache grows over its limit.
+    pub fn store(&self, data: &Data) {
+        let key = key(&data.state);
+        let path = self.path(&key);
+        // a reader never sees a partly written file, as it is only renamed
+        // into place once complete
+        let temp = path.with_extension(format!("tmp{}_{}", std::process::id(), self.next_temp.fetch_add(1, Ordering::Relaxed)));
+        // the same render may be stored again, replacing a file already counted
+        let replaced = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+        match write_data(&temp, &key, data).and_then(|len| fs::rename(&temp, &path).map(|_| len)) {
+            Ok(len) => {
+                let resize = |size: u64| Some((size + len).saturating_sub(replaced));
+                let size = self.size.fetch_update(Ordering::Relaxed, Ordering::Relaxed, resize).unwrap();
+                if (size + len).saturating_sub(replaced) > self.limit {
+                    self.evict();
+                }
+            }
+            Err(err) => {
+                fs::remove_file(&temp).ok();
+                println!("failed to cache {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// The results of state from the cache, or computed and cached.
+    pub fn compute(&self, state: State) -> Data {
+        if let Some(data) = self.load(&state) {
+            return data;
+        }
+        let mut data = Data::new(state);
+        compute_mandelbrot(&mut data);
+        self.store(&data);
+        data
+    }
+
+    fn evict(&self) {
+        // another thread is already evicting
+        let _evicting = match self.evicting.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+        let mut files = match files(&self.dir) {
+            Ok(files) => files,
+            Err(_) => return,
+        };
+        files.sort_by_key(|(_, _, modified)| *modified);
+        let mut size: u64 = files.iter().map(|(_, len, _)| len).sum();
+        let target = (self.limit as f64 * EVICT_TO) as u64;
+        for (path, len, _) in files {
+            if size <= target {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                size -= len;
+            }
+        }
+        self.size.store(size, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fractal_rs_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn storing_a_render_again_does_not_count_it_twice() {
+        let root = test_dir("restore");
+        let cache = DiskCache::open(&root, 1 << 30).unwrap();
+        let mut data = Data::new(State::new(16, 8));
+        compute_mandelbrot(&mut data);
+        cache.store(&data);
+        cache.store(&data);
+        let on_disk: u64 = files(&cache.dir).unwrap().iter().map(|(_, len, _)| len).sum();
+        assert_eq!(cache.size.load(Ordering::Relaxed), on_disk);
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn files_with_a_bad_header_are_rejected() {
+        let state = State::new(16, 8);
+        let key = key(&state);
+        let mut huge_key = MAGIC.to_vec();
+        huge_key.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_data(&huge_key[..], &key, &state).is_err());
+        let mut bad_magic = b"FRACTAL0".to_vec();
+        bad_magic.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        bad_magic.extend_from_slice(key.as_bytes());
+        assert!(read_data(&bad_magic[..], &key, &state).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use std::f64::consts::{LN_10, LN_2, PI, TAU};
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use eframe::egui::{Color32, ColorImage};
+use bigdecimal::BigDecimal;
+use rayon::prelude::*;
+use crate::animation::{interpolate_view, progress};
+use crate::bigcoord;
+use crate::export;
+use crate::mandelbrot::{Sampling, State};
+use crate::palette::PaletteType;
+use crate::params;
+use crate::render::render_to_image;
+
+/// The file in a strip directory describing the zoom the strips cover.
+const INFO_FILE: &str = "expmap.txt";
+
+/// The number of rows a log polar render of the given width needs to cover
+/// one octave of zoom with square pixels.
+pub fn rows_per_octave(width: u32) -> u32 {
+    (width as f64 * LN_2 / TAU).ceil() as u32
+}
+
+fn strip_path(dir: &Path, k: u32) -> PathBuf {
+    dir.join(format!("strip_{:05}.png", k))
+}
+
+/// A zoom into a single point rendered as log polar strips, one per octave,
+/// from which the frames of the zoom are put back together.
+///
+/// Every frame of a zoom shows the same rings around the center at a
+/// different size, so each ring only has to be computed once instead of for
+/// every frame it appears in.
+pub struct ExpMap {
+    /// The last frame of the zoom, its size is the size of every frame.
+    pub end: State,
+    pub start_scale: BigDecimal,
+    pub palette: PaletteType,
+}
+
+impl ExpMap {
+    /// The log polar render of the outermost strip.
+    ///
+    /// Its circle passes through the corners of the first frame, and it has
+    /// as many samples around as there are frame pixels along that circle.
+    fn first_strip(&self) -> State {
+        let (w, h) = (self.end.width as f64, self.end.height as f64);
+        let diagonal = (w * w + h * h).sqrt();
+        let mut first = self.end.clone();
+        first.sampling = Sampling::LogPolar;
+        first.width = (PI * diagonal).ceil() as u32;
+        first.height = rows_per_octave(first.width);
+        first.scale = bigcoord::scale_by(&self.start_scale, diagonal / w);
+        first
+    }
+
+    /// The number of strips needed to reach from the corners of the first
+    /// frame to within half a pixel of the center of the last.
+    pub fn strip_count(&self) -> u32 {
+        let first = self.first_strip();
+        // the log of the ratio of the outer radius to half a pixel of the last
+        // frame, worked out in logs as the scales can be beyond f64
+        let zoom = bigcoord::log10(&first.scale) - bigcoord::log10(&self.end.scale) + (self.end.width as f64).log10();
+        let rows = first.width as f64 / TAU * zoom * LN_10;
+        ((rows / first.height as f64).ceil() as u32).max(1)
+    }
+
+    /// The render of strip k, which continues inward where strip k - 1
+    /// ended.
+    ///
+    /// Every strip uses the iteration limit of the last frame, as linear
+    /// palettes are scaled by the limit and a ramp would leave seams where
+    /// the strips meet.
+    pub fn strip_state(&self, k: u32) -> State {
+        let mut state = self.first_strip();
+        let rows = (k * state.height) as f64;
+        state.scale = bigcoord::pow10(bigcoord::log10(&state.scale) - TAU * rows / state.width as f64 / LN_10);
+        state
+    }
+
+    /// Render every strip to strip_NNNNN.png files in dir, along with the
+    /// description of the zoom, calling on_strip with the number of strips
+    /// written so far.
+    pub fn render_strips(&self, dir: &Path, on_strip: &mut dyn FnMut(u32)) -> io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(INFO_FILE), self.to_params())?;
+        let palette = self.palette.to_palette();
+        for k in 0..self.strip_count() {
+            let image = render_to_image(self.strip_state(k), &palette);
+            export::save_png(&strip_path(dir, k), &image)?;
+            on_strip(k + 1);
+        }
+        Ok(())
+    }
+
+    pub fn to_params(&self) -> String {
+        let mut out = String::from("[expmap]\n");
+        params::write_state(&mut out, &self.end);
+        writeln!(out, "start_scale = {}", self.start_scale).unwrap();
+        writeln!(out, "palette = {:?}", self.palette).unwrap();
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let section = params::parse(text)?
+            .into_iter()
+            .find(|s| s.name == "expmap")
+            .ok_or_else(|| String::from("no [expmap] section found"))?;
+        let mut end = State::new(1, 1);
+        let mut start_scale = None;
+        let mut palette = None;
+        for (name, value) in section.values.iter() {
+            if params::apply_state_value(&mut end, name, value)? {
+                continue;
+            }
+            let bad = || format!("bad value for {}: `{}`", name, value);
+            match name.as_str() {
+                "start_scale" => start_scale = Some(value.parse().map_err(|_| bad())?),
+                "palette" => palette = Some(PaletteType::from_name(value).ok_or_else(bad)?),
+                _ => return Err(format!("unknown expmap value `{}`", name)),
+            }
+        }
+        Ok(Self {
+            start_scale: start_scale.ok_or("start_scale is missing")?,
+            palette: palette.unwrap_or(PaletteType::Color1Lin),
+            end,
+        })
+    }
+
+    /// Read the description of the zoom rendered into dir.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let path = dir.join(INFO_FILE);
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Put frames frames of the zoom back together from the strips in dir,
+    /// writing them to frame_NNNNN.png files in out.
+    pub fn render_frames(&self, dir: &Path, frames: u32, out: &Path, on_frame: &mut dyn FnMut(u32)) -> io::Result<()> {
+        std::fs::create_dir_all(out)?;
+        let mut strips = Strips::new(self, dir);
+        for i in 0..frames {
+            let t = progress(i, frames);
+            let mut view = self.end.clone();
+            (_, view.scale) = interpolate_view(&self.end.center, &self.start_scale, &self.end.center, &self.end.scale, t);
+            let image = strips.frame(&view)?;
+            export::save_png(&export::frame_path(out, i), &image)?;
+            on_frame(i + 1);
+        }
+        Ok(())
+    }
+}
+
+/// The strips of a zoom, loaded from disk as frames need them.
+struct Strips {
+    dir: PathBuf,
+    first: State,
+    /// The base 10 log of the outer radius of the first strip.
+    log_outer: f64,
+    loaded: Vec<Option<ColorImage>>,
+}
+
+impl Strips {
+    fn new(map: &ExpMap, dir: &Path) -> Self {
+        let first = map.first_strip();
+        Self {
+            dir: dir.to_path_buf(),
+            log_outer: bigcoord::log10(&first.scale) - 2f64.log10(),
+            first,
+            loaded: (0..map.strip_count()).map(|_| None).collect(),
+        }
+    }
+
+    /// The row, counting across all strips, at the radius whose base 10 log
+    /// is log_radius, so radii beyond f64 can be used. Fractional rows lie
+    /// between two rows.
+    fn row_at(&self, log_radius: f64) -> f64 {
+        self.first.width as f64 / TAU * (self.log_outer - log_radius) * LN_10
+    }
+
+    /// Load the strips covering radii from outer to inner, as base 10 logs,
+    /// and drop the rest, so only the strips of the current frame are held
+    /// in memory.
+    fn load_range(&mut self, log_outer: f64, log_inner: f64) -> io::Result<()> {
+        let rows = self.first.height as f64;
+        let last = self.loaded.len() as i64 - 1;
+        let first_needed = ((self.row_at(log_outer) / rows).floor() as i64).clamp(0, last) as usize;
+        let last_needed = ((self.row_at(log_inner) / rows).floor() as i64 + 1).clamp(0, last) as usize;
+        for (k, strip) in self.loaded.iter_mut().enumerate() {
+            if k < first_needed || k > last_needed {
+                *strip = None;
+            } else if strip.is_none() {
+                *strip = Some(export::load_png(&strip_path(&self.dir, k as u32))?);
+            }
+        }
+        Ok(())
+    }
+
+    fn frame(&mut self, view: &State) -> io::Result<ColorImage> {
+        let (w, h) = (view.width as f64, view.height as f64);
+        // radii are worked out in pixels of the view and then in logs, as the
+        // size of a pixel can be beyond f64
+        let log_pixel = bigcoord::log10(&view.scale) - w.log10();
+        self.load_range(log_pixel + ((w * w + h * h).sqrt() / 2.0).log10(), log_pixel + 0.5f64.log10())?;
+        let (center_x, center_y) = ((view.width / 2) as f64, (view.height / 2) as f64);
+
+        let mut image = ColorImage::new([view.width as usize, view.height as usize], Color32::BLACK);
+        let strips = &*self;
+        image
+            .pixels
+            .par_chunks_mut(view.width as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let (dx, dy) = (x as f64 - center_x, center_y - y as f64);
+                    // the center pixel itself takes the innermost row
+                    let distance = dx.hypot(dy).max(f64::MIN_POSITIVE);
+                    let angle = (dy.atan2(dx) + view.rotation - strips.first.rotation) / TAU * strips.first.width as f64;
+                    *pixel = strips.sample(angle, strips.row_at(log_pixel + distance.log10()));
+                }
+            });
+        Ok(image)
+    }
+
+    /// Bilinearly interpolate the strips at a fractional column and row.
+    fn sample(&self, column: f64, row: f64) -> Color32 {
+        let width = self.first.width as i64;
+        let total_rows = self.loaded.len() as i64 * self.first.height as i64;
+        let (x0, y0) = (column.floor(), row.floor());
+        let (fx, fy) = ((column - x0) as f32, (row - y0) as f32);
+        let texel = |x: i64, y: i64| {
+            let x = x.rem_euclid(width) as usize;
+            let y = y.clamp(0, total_rows - 1);
+            let (k, y) = ((y / self.first.height as i64) as usize, (y % self.first.height as i64) as usize);
+            match self.loaded[k].as_ref() {
+                Some(strip) => strip.pixels[y * strip.size[0] + x],
+                None => Color32::BLACK,
+            }
+        };
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = mix(texel(x0, y0), texel(x0 + 1, y0), fx);
+        let bottom = mix(texel(x0, y0 + 1), texel(x0 + 1, y0 + 1), fx);
+        mix(top, bottom, fy)
+    }
+}
+
+fn mix(a: Color32, b: Color32, t: f32) -> Color32 {
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t).round() as u8;
+    Color32::from_rgb(channel(a.r(), b.r()), channel(a.g(), b.g()), channel(a.b(), b.b()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bigcoord::BigComplex;
+
+    /// A zoom ending far beyond the range of f64.
+    fn deep_zoom() -> ExpMap {
+        let mut end = State::new(64, 48);
+        end.center = BigComplex::parse("-0.75, 0.1").unwrap();
+        end.scale = "1e-400".parse().unwrap();
+        ExpMap {
+            end,
+            start_scale: BigDecimal::from(4),
+            palette: PaletteType::Color1Lin,
+        }
+    }
+
+    #[test]
+    fn strip_rows_map_back_to_their_scales() {
+        let map = deep_zoom();
+        let strips = Strips::new(&map, Path::new(""));
+        let rows = strips.first.height as f64;
+        for k in 0..map.strip_count() {
+            let log_outer = bigcoord::log10(&map.strip_state(k).scale) - 2f64.log10();
+            let row = strips.row_at(log_outer);
+            assert!((row - k as f64 * rows).abs() < 1e-6 * rows.max(row), "strip {} starts at row {}", k, row);
+        }
+    }
+
+    #[test]
+    fn strips_reach_half_a_pixel_of_the_last_frame() {
+        let map = deep_zoom();
+        let strips = Strips::new(&map, Path::new(""));
+        let half_pixel = bigcoord::log10(&map.end.scale) - (map.end.width as f64).log10() + 0.5f64.log10();
+        let rows = map.strip_count() as f64 * strips.first.height as f64;
+        let row = strips.row_at(half_pixel);
+        assert!(row <= rows && row > rows - strips.first.height as f64, "row {} of {}", row, rows);
+    }
+
+    #[test]
+    fn frames_are_put_back_together_from_the_strips() {
+        let dir = std::env::temp_dir().join(format!("fractal_rs_expmap_{}", std::process::id()));
+        let mut end = State::new(48, 36);
+        end.center = BigComplex::parse("-0.6, 0.45").unwrap();
+        end.scale = BigDecimal::from(1);
+        let map = ExpMap {
+            end: end.clone(),
+            start_scale: BigDecimal::from(2),
+            palette: PaletteType::Color1Lin,
+        };
+        map.render_strips(&dir.join("strips"), &mut |_| {}).unwrap();
+        map.render_frames(&dir.join("strips"), 2, &dir.join("frames"), &mut |_| {}).unwrap();
+        let frame = export::load_png(&export::frame_path(&dir.join("frames"), 1)).unwrap();
+        let direct = render_to_image(end, &map.palette.to_palette());
+        std::fs::remove_dir_all(&dir).ok();
+        // resampling the strips blurs edges, so compare the average difference
+        let difference: f64 = frame
+            .pixels
+            .iter()
+            .zip(direct.pixels.iter())
+            .map(|(a, b)| a.r().abs_diff(b.r()) as f64 + a.g().abs_diff(b.g()) as f64 + a.b().abs_diff(b.b()) as f64)
+            .sum::<f64>()
+            / (3 * frame.pixels.len()) as f64;
+        assert!(difference < 8.0, "average difference {}", difference);
+    }
+}
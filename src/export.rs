@@ -0,0 +1,134 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use eframe::egui::ColorImage;
+use crate::mandelbrot::{compute_mandelbrot_with_progress, Data, EscapeSample, State};
+use crate::palette::Palette;
+use crate::progress::Progress;
+use crate::supersample::{self, Supersampling};
+
+/// The number of pixels computed at once by save_png_bands, about 16MB of
+/// escape counts.
+const BAND_PIXELS: u32 = 4_000_000;
+
+/// Write an image to a PNG file.
+pub fn save_png(path: &Path, image: &ColorImage) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.size[0] as u32, image.size[1] as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    let data: Vec<u8> = image.pixels.iter().flat_map(|p| [p.r(), p.g(), p.b()]).collect();
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// The number of rows of a band of BAND_PIXELS pixels for images width
+/// pixels wide.
+pub fn default_band_rows(width: u32) -> u32 {
+    (BAND_PIXELS / width.max(1)).max(1)
+}
+
+/// Compute, color and write state to a PNG file band_rows rows at a time,
+/// so images of any size can be exported in fixed memory. Bands keep only
+/// escape counts, and are never put in the disk cache, where they would push
+/// out the renders worth keeping. progress counts the rows written.
+pub fn save_png_bands(
+    path: &Path,
+    state: &State,
+    pal: &Palette,
+    band_rows: u32,
+    supersampling: &Supersampling,
+    progress: &Progress,
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, state.width, state.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    let mut stream = writer.stream_writer_with_size(1 << 20).map_err(io::Error::other)?;
+    progress.start(state.height as u64);
+    let mut first = 0;
+    while first < state.height {
+        let rows = band_rows.max(1).min(state.height - first);
+        // a row either side of the band, so edges on its first and last rows
+        // are found as they would be in the whole image
+        let above = first.min(1);
+        let below = (state.height - first - rows).min(1);
+        let band = state.band(first - above, above + rows + below);
+        let inner = above as usize..(above + rows) as usize;
+        let first_row = (first - above) as usize;
+        let mut data: Data<EscapeSample> = Data::new(band);
+        compute_mandelbrot_with_progress(&mut data, progress);
+        let image = supersample::render_band(&data, inner, first_row, supersampling, pal);
+        let bytes: Vec<u8> = image.pixels.iter().flat_map(|p| [p.r(), p.g(), p.b()]).collect();
+        stream.write_all(&bytes)?;
+        first += rows;
+        progress.set(first as u64);
+    }
+    stream.finish().map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// Write frames frames to an animated PNG that loops forever, asking
+/// next_frame for each in turn. Every frame must be width by height.
+pub fn save_apng(
+    path: &Path,
+    width: u32,
+    height: u32,
+    frames: u32,
+    fps: u16,
+    next_frame: &mut dyn FnMut(u32) -> ColorImage,
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames, 0).map_err(io::Error::other)?;
+    encoder.set_frame_delay(1, fps).map_err(io::Error::other)?;
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    for i in 0..frames {
+        let image = next_frame(i);
+        let data: Vec<u8> = image.pixels.iter().flat_map(|p| [p.r(), p.g(), p.b()]).collect();
+        writer.write_image_data(&data).map_err(io::Error::other)?;
+    }
+    writer.finish().map_err(io::Error::other)
+}
+
+/// A file name in the working directory that does not exist yet, such as
+/// `fractal_0003.png`. An empty extension gives a bare name, for directories.
+pub fn next_file_name(prefix: &str, extension: &str) -> PathBuf {
+    let mut i = 0;
+    loop {
+        let path = match extension {
+            "" => PathBuf::from(format!("{}_{:04}", prefix, i)),
+            _ => PathBuf::from(format!("{}_{:04}.{}", prefix, i, extension)),
+        };
+        if !path.exists() {
+            return path;
+        }
+        i += 1;
+    }
+}
+
+/// The name of frame number i of an image sequence in dir.
+pub fn frame_path(dir: &Path, i: u32) -> PathBuf {
+    dir.join(format!("frame_{:05}.png", i))
+}
+
+/// Read an 8 bit RGB or RGBA PNG file, such as one written by save_png.
+pub fn load_png(path: &Path) -> io::Result<ColorImage> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(io::Error::other)?;
+    let size = [info.width as usize, info.height as usize];
+    let data = &data[..info.buffer_size()];
+    match info.color_type {
+        png::ColorType::Rgb => Ok(ColorImage::from_rgb(size, data)),
+        png::ColorType::Rgba => Ok(ColorImage::from_rgba_unmultiplied(size, data)),
+        other => Err(io::Error::other(format!("unsupported PNG color type {:?}", other))),
+    }
+}
@@ -0,0 +1,194 @@
+use eframe::epaint::TextureHandle;
+use crate::mandelbrot::State;
+use crate::palette::PaletteType;
+
+const MAX_ENTRIES: usize = 200;
+const MAX_CACHED_TEXTURES: usize = 16;
+
+pub struct HistoryEntry {
+    pub state: State,
+    pub palette: PaletteType,
+    pub texture: Option<TextureHandle>,
+}
+
+/// A linear undo/redo stack of the views that have been rendered.
+///
+/// Entries close to the current position keep their rendered texture so
+/// stepping back and forth does not need a new render.
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    position: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            position: 0,
+        }
+    }
+
+    pub fn current(&self) -> Option<&HistoryEntry> {
+        self.entries.get(self.position)
+    }
+
+    /// Add a new view after the current one, discarding any forward history.
+    pub fn push(&mut self, state: State, palette: PaletteType, texture: TextureHandle) {
+        if !self.entries.is_empty() {
+            self.entries.truncate(self.position + 1);
+        }
+        self.entries.push(HistoryEntry {
+            state,
+            palette,
+            texture: Some(texture),
+        });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.position = self.entries.len() - 1;
+        self.evict_textures();
+    }
+
+    /// Replace the current entry in place, used when the same view is
+    /// re-rendered at a new size.
+    pub fn replace_current(&mut self, state: State, palette: PaletteType, texture: TextureHandle) {
+        match self.entries.get_mut(self.position) {
+            Some(entry) => {
+                entry.state = state;
+                entry.palette = palette;
+                entry.texture = Some(texture);
+            }
+            None => self.push(state, palette, texture),
+        }
+    }
+
+    /// Store a freshly rendered texture for the current entry.
+    pub fn set_current_texture(&mut self, texture: TextureHandle) {
+        if let Some(entry) = self.entries.get_mut(self.position) {
+            entry.texture = Some(texture);
+        }
+        self.evict_textures();
+    }
+
+    pub fn can_go_back(&self) -> bool {
+        self.position > 0
+    }
+
+    pub fn can_go_forward(&self) -> bool {
+        self.position + 1 < self.entries.len()
+    }
+
+    pub fn back(&mut self) -> Option<&HistoryEntry> {
+        if !self.can_go_back() {
+            return None;
+        }
+        self.position -= 1;
+        self.entries.get(self.position)
+    }
+
+    pub fn forward(&mut self) -> Option<&HistoryEntry> {
+        if !self.can_go_forward() {
+            return None;
+        }
+        self.position += 1;
+        self.entries.get(self.position)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Drop the textures of the entries furthest from the current position
+    /// so only MAX_CACHED_TEXTURES stay resident.
+    fn evict_textures(&mut self) {
+        let mut cached: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.texture.is_some())
+            .map(|(i, _)| i)
+            .collect();
+        if cached.len() <= MAX_CACHED_TEXTURES {
+            return;
+        }
+        let position = self.position;
+        cached.sort_by_key(|i| std::cmp::Reverse(i.abs_diff(position)));
+        let excess = cached.len() - MAX_CACHED_TEXTURES;
+        for i in cached.into_iter().take(excess) {
+            self.entries[i].texture = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eframe::egui::{Color32, ColorImage, Context};
+    use super::*;
+
+    fn texture(ctx: &Context) -> TextureHandle {
+        ctx.load_texture("history", ColorImage::new([1, 1], Color32::BLACK), Default::default())
+    }
+
+    /// A view told apart from the others by its iteration limit.
+    fn view(i: u32) -> State {
+        let mut state = State::new(8, 8);
+        state.max_iterations = i;
+        state
+    }
+
+    fn current(history: &History) -> u32 {
+        history.current().unwrap().state.max_iterations
+    }
+
+    #[test]
+    fn back_and_forward_step_through_the_views() {
+        let ctx = Context::default();
+        let mut history = History::new();
+        assert!(history.current().is_none() && history.back().is_none());
+        for i in 1..=3 {
+            history.push(view(i), PaletteType::BW, texture(&ctx));
+        }
+        assert_eq!(current(&history), 3);
+        assert!(history.forward().is_none());
+        assert_eq!(history.back().unwrap().state.max_iterations, 2);
+        assert_eq!(history.back().unwrap().state.max_iterations, 1);
+        assert!(history.back().is_none());
+        assert_eq!(history.forward().unwrap().state.max_iterations, 2);
+        assert_eq!((history.position(), history.len()), (1, 3));
+    }
+
+    #[test]
+    fn pushing_after_going_back_drops_the_forward_views() {
+        let ctx = Context::default();
+        let mut history = History::new();
+        for i in 1..=3 {
+            history.push(view(i), PaletteType::BW, texture(&ctx));
+        }
+        history.back();
+        history.back();
+        history.push(view(4), PaletteType::BW, texture(&ctx));
+        assert_eq!(history.len(), 2);
+        assert!(!history.can_go_forward());
+        assert_eq!(current(&history), 4);
+        assert_eq!(history.back().unwrap().state.max_iterations, 1);
+    }
+
+    #[test]
+    fn old_views_and_distant_textures_are_dropped() {
+        let ctx = Context::default();
+        let mut history = History::new();
+        for i in 0..MAX_ENTRIES as u32 + 10 {
+            history.push(view(i), PaletteType::BW, texture(&ctx));
+        }
+        assert_eq!(history.len(), MAX_ENTRIES);
+        assert_eq!(history.entries[0].state.max_iterations, 10);
+        let cached: Vec<usize> = (0..history.len()).filter(|&i| history.entries[i].texture.is_some()).collect();
+        assert_eq!(cached, (MAX_ENTRIES - MAX_CACHED_TEXTURES..MAX_ENTRIES).collect::<Vec<_>>());
+        history.replace_current(view(1000), PaletteType::BW, texture(&ctx));
+        assert_eq!((current(&history), history.len()), (1000, MAX_ENTRIES));
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt::Display;
+use eframe::egui;
+use egui::{Key, KeyboardShortcut, Modifiers};
+
+/// The keymap file read from the working directory when it exists.
+pub const KEYMAP_FILE: &str = "fractal_keymap.txt";
+/// Environment variable overriding the location of the keymap file.
+pub const KEYMAP_ENV: &str = "FRACTAL_KEYMAP";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    ZoomIn,
+    ZoomOut,
+    MoreIterations,
+    FewerIterations,
+    CyclePalette,
+    ToggleFractal,
+    ResetView,
+    Back,
+    Forward,
+}
+
+const ACTIONS: [Action; 13] = [
+    Action::PanLeft,
+    Action::PanRight,
+    Action::PanUp,
+    Action::PanDown,
+    Action::ZoomIn,
+    Action::ZoomOut,
+    Action::MoreIterations,
+    Action::FewerIterations,
+    Action::CyclePalette,
+    Action::ToggleFractal,
+    Action::ResetView,
+    Action::Back,
+    Action::Forward,
+];
+
+impl Action {
+    /// The name used for the action in keymap files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::PanLeft => "pan_left",
+            Action::PanRight => "pan_right",
+            Action::PanUp => "pan_up",
+            Action::PanDown => "pan_down",
+            Action::ZoomIn => "zoom_in",
+            Action::ZoomOut => "zoom_out",
+            Action::MoreIterations => "more_iterations",
+            Action::FewerIterations => "fewer_iterations",
+            Action::CyclePalette => "cycle_palette",
+            Action::ToggleFractal => "toggle_fractal",
+            Action::ResetView => "reset_view",
+            Action::Back => "back",
+            Action::Forward => "forward",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().copied().find(|a| a.name() == name)
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+pub struct Keymap {
+    bindings: Vec<(KeyboardShortcut, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let none = Modifiers::NONE;
+        let cmd = Modifiers::COMMAND;
+        let bindings = vec![
+            (KeyboardShortcut::new(none, Key::ArrowLeft), Action::PanLeft),
+            (KeyboardShortcut::new(none, Key::ArrowRight), Action::PanRight),
+            (KeyboardShortcut::new(none, Key::ArrowUp), Action::PanUp),
+            (KeyboardShortcut::new(none, Key::ArrowDown), Action::PanDown),
+            (KeyboardShortcut::new(none, Key::Plus), Action::ZoomIn),
+            (KeyboardShortcut::new(none, Key::Equals), Action::ZoomIn),
+            (KeyboardShortcut::new(none, Key::PageUp), Action::ZoomIn),
+            (KeyboardShortcut::new(none, Key::Minus), Action::ZoomOut),
+            (KeyboardShortcut::new(none, Key::PageDown), Action::ZoomOut),
+            (KeyboardShortcut::new(none, Key::CloseBracket), Action::MoreIterations),
+            (KeyboardShortcut::new(none, Key::OpenBracket), Action::FewerIterations),
+            (KeyboardShortcut::new(none, Key::P), Action::CyclePalette),
+            (KeyboardShortcut::new(none, Key::J), Action::ToggleFractal),
+            (KeyboardShortcut::new(none, Key::Home), Action::ResetView),
+            (KeyboardShortcut::new(cmd, Key::Z), Action::Back),
+            (KeyboardShortcut::new(Modifiers::ALT, Key::ArrowLeft), Action::Back),
+            (KeyboardShortcut::new(cmd | Modifiers::SHIFT, Key::Z), Action::Forward),
+            (KeyboardShortcut::new(cmd, Key::Y), Action::Forward),
+            (KeyboardShortcut::new(Modifiers::ALT, Key::ArrowRight), Action::Forward),
+        ];
+        Self::from_bindings(bindings)
+    }
+}
+
+impl Keymap {
+    fn from_bindings(mut bindings: Vec<(KeyboardShortcut, Action)>) -> Self {
+        // egui ignores extra shift/alt when matching, so the shortcuts with
+        // the most modifiers have to be checked first
+        bindings.sort_by_key(|(shortcut, _)| std::cmp::Reverse(modifier_count(shortcut.modifiers)));
+        Self { bindings }
+    }
+
+    /// Load the keymap file named by KEYMAP_ENV, or KEYMAP_FILE. The default
+    /// keymap is used when there is no file or it cannot be parsed.
+    pub fn load() -> Self {
+        let path = std::env::var(KEYMAP_ENV).unwrap_or_else(|_| String::from(KEYMAP_FILE));
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return Self::default(),
+        };
+        match Self::parse(&text) {
+            Ok(keymap) => {
+                println!("loaded keymap from {}", path);
+                keymap
+            }
+            Err(err) => {
+                println!("ignoring keymap {}: {}", path, err);
+                Self::default()
+            }
+        }
+    }
+
+    /// Parse a keymap file made of `shortcut = action` lines, such as
+    /// `Ctrl+Shift+Z = forward`. Blank lines and lines starting with `#` are
+    /// ignored. Actions bound in the file replace their default bindings.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut overrides: Vec<(KeyboardShortcut, Action)> = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (shortcut, action) = line
+                .rsplit_once('=')
+                .ok_or_else(|| format!("line {}: expected `shortcut = action`", line_no + 1))?;
+            let action = Action::from_name(action.trim())
+                .ok_or_else(|| format!("line {}: unknown action `{}`", line_no + 1, action.trim()))?;
+            let shortcut = parse_shortcut(shortcut.trim())
+                .ok_or_else(|| format!("line {}: unknown shortcut `{}`", line_no + 1, shortcut.trim()))?;
+            overrides.push((shortcut, action));
+        }
+        let mut bindings: Vec<(KeyboardShortcut, Action)> = Self::default()
+            .bindings
+            .into_iter()
+            .filter(|(_, action)| !overrides.iter().any(|(_, a)| a == action))
+            .collect();
+        bindings.append(&mut overrides);
+        Ok(Self::from_bindings(bindings))
+    }
+
+    /// Consume the key presses of this frame, returning the actions they
+    /// trigger in the order they were found.
+    pub fn pressed_actions(&self, ctx: &egui::Context) -> Vec<Action> {
+        if ctx.wants_keyboard_input() {
+            return Vec::new();
+        }
+        ctx.input_mut(|i| {
+            self.bindings
+                .iter()
+                .filter(|(shortcut, _)| i.consume_shortcut(shortcut))
+                .map(|(_, action)| *action)
+                .collect()
+        })
+    }
+
+    /// The shortcuts bound to an action, formatted for display.
+    pub fn describe(&self, ctx: &egui::Context, action: Action) -> String {
+        self.bindings
+            .iter()
+            .filter(|(_, a)| *a == action)
+            .map(|(shortcut, _)| ctx.format_shortcut(shortcut))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    pub fn actions() -> &'static [Action] {
+        &ACTIONS
+    }
+}
+
+fn modifier_count(m: Modifiers) -> u32 {
+    m.alt as u32 + m.shift as u32 + (m.ctrl || m.command || m.mac_cmd) as u32
+}
+
+/// Parse a shortcut such as `Ctrl+Shift+Z`, `Alt+Left` or `PageUp`.
+fn parse_shortcut(text: &str) -> Option<KeyboardShortcut> {
+    // a trailing "+" is the plus key itself, as in "Ctrl++"
+    let (mods, key) = match text.strip_suffix("++") {
+        Some(mods) => (mods, "+"),
+        None => match text.rsplit_once('+') {
+            Some((mods, key)) if !mods.is_empty() => (mods, key),
+            _ => ("", text),
+        },
+    };
+    let mut modifiers = Modifiers::NONE;
+    for m in mods.split('+').filter(|m| !m.is_empty()) {
+        modifiers = modifiers
+            | match m.trim().to_lowercase().as_str() {
+                "ctrl" | "control" | "cmd" | "command" => Modifiers::COMMAND,
+                "alt" | "option" => Modifiers::ALT,
+                "shift" => Modifiers::SHIFT,
+                _ => return None,
+            };
+    }
+    let key = key.trim();
+    let key = Key::from_name(key).or_else(|| Key::from_name(&key.to_uppercase()))?;
+    Some(KeyboardShortcut::new(modifiers, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shortcuts(keymap: &Keymap, action: Action) -> Vec<KeyboardShortcut> {
+        keymap.bindings.iter().filter(|(_, a)| *a == action).map(|(s, _)| *s).collect()
+    }
+
+    #[test]
+    fn bound_actions_replace_their_defaults() {
+        let keymap = Keymap::parse("# pan with vi keys\n\nh = pan_left\nCtrl+Shift+H = pan_left\n").unwrap();
+        assert_eq!(
+            shortcuts(&keymap, Action::PanLeft),
+            [
+                KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::H),
+                KeyboardShortcut::new(Modifiers::NONE, Key::H),
+            ]
+        );
+        assert_eq!(shortcuts(&keymap, Action::PanRight), shortcuts(&Keymap::default(), Action::PanRight));
+    }
+
+    #[test]
+    fn shortcuts_with_more_modifiers_come_first() {
+        let keymap = Keymap::default();
+        let counts: Vec<u32> = keymap.bindings.iter().map(|(s, _)| modifier_count(s.modifiers)).collect();
+        assert!(counts.windows(2).all(|pair| pair[0] >= pair[1]), "{:?}", counts);
+    }
+
+    #[test]
+    fn shortcuts_are_parsed() {
+        assert_eq!(parse_shortcut("PageUp"), Some(KeyboardShortcut::new(Modifiers::NONE, Key::PageUp)));
+        assert_eq!(parse_shortcut("alt+Left"), Some(KeyboardShortcut::new(Modifiers::ALT, Key::ArrowLeft)));
+        assert_eq!(parse_shortcut("Ctrl++"), Some(KeyboardShortcut::new(Modifiers::COMMAND, Key::Plus)));
+        assert_eq!(parse_shortcut("+"), Some(KeyboardShortcut::new(Modifiers::NONE, Key::Plus)));
+        assert_eq!(parse_shortcut("Hyper+Z"), None);
+        assert_eq!(parse_shortcut("Ctrl+Nothing"), None);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = |text| Keymap::parse(text).err().unwrap();
+        assert_eq!(error("p = cycle_palette\nq"), "line 2: expected `shortcut = action`");
+        assert_eq!(error("q = quit"), "line 1: unknown action `quit`");
+        assert_eq!(error("\nSuper+Q = back"), "line 2: unknown shortcut `Super+Q`");
+    }
+}
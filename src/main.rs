@@ -1,13 +1,39 @@
+mod animation;
+mod bigcoord;
+mod cli;
+mod diskcache;
+mod export;
+mod expmap;
+mod history;
+mod keymap;
 mod mandelbrot;
+mod overlay;
 mod palette;
+mod params;
+mod precision;
+mod progress;
+mod render;
+mod simd;
+mod supersample;
+mod tiles;
 
 
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::thread;
 
-use crate::mandelbrot::{compute_mandelbrot, FractalType, State};
-use num_complex::{Complex64};
+use crate::bigcoord::BigComplex;
+use crate::history::History;
+use crate::keymap::{Action, Keymap};
+use crate::mandelbrot::{compute_mandelbrot_with_progress, Complex64, FractalType, Precision, Sample};
+use crate::overlay::OverlayOptions;
+use crate::progress::Progress;
+use crate::render::{render_region, render_to_image};
+use crate::supersample::{Pattern, Supersamples, Supersampling};
 
+use bigdecimal::{BigDecimal, RoundingMode};
 use eframe::{App, Frame};
 use eframe::egui;
 use eframe::egui::{ColorImage, Context, Sense};
@@ -16,92 +42,315 @@ use eframe::epaint::TextureHandle;
 
 const WIDTH:u32 = 1024;
 const HEIGHT:u32 = 768;
+/// The raw scroll delta, in points, of a single mouse wheel notch.
+const SCROLL_NOTCH: f32 = 50.0;
+/// Rectangles smaller than this many pixels across are ignored, as they are
+/// almost certainly accidental.
+const MIN_ZOOM_RECT: f32 = 4.0;
+/// The fraction of the view the arrow keys pan by.
+const PAN_FRACTION: f64 = 0.1;
+const ITERATION_STEP: u32 = 50;
+const PREVIEW_WIDTH: u32 = 192;
+const PREVIEW_HEIGHT: u32 = 144;
+const PREVIEW_MAX_ITERATIONS: u32 = 150;
+const MINIMAP_WIDTH: u32 = 200;
+const MINIMAP_HEIGHT: u32 = 150;
+/// Views smaller than this on the minimap are marked with a crosshair
+/// instead of a rectangle.
+const MINIMAP_MIN_RECT: f32 = 4.0;
+/// The frames between keyframes added from the viewer.
+const KEYFRAME_FRAMES: u32 = 60;
+const MORPH_WIDTH: u32 = 320;
+const MORPH_HEIGHT: u32 = 240;
+/// The distance in pixels the mouse must move to add a point to a drawn
+/// morph path.
+const MORPH_PATH_STEP: f32 = 3.0;
+const MORPH_CIRCLE_POINTS: u32 = 256;
+/// The frame rate of exported palette cycles.
+const CYCLE_EXPORT_FPS: u16 = 25;
+/// The width of the center and scale fields, wide enough for deep zooms.
+const VIEW_TEXT_WIDTH: f32 = 260.0;
+const PROGRESS_WIDTH: f32 = 260.0;
+/// How often the progress bar is redrawn while nothing else changes.
+const PROGRESS_REPAINT: std::time::Duration = std::time::Duration::from_millis(200);
+
 
 fn cycle_palette(p: palette::Palette) -> palette::Palette {
-    return match p.palette_type {
+    match p.palette_type {
         palette::PaletteType::BW => palette::new_color1_lin(),
         palette::PaletteType::Color1Lin => palette::new_color1_mod(),
         palette::PaletteType::Color1Mod => palette::new_color2_lin(),
         palette::PaletteType::Color2Lin => palette::new_color2_mod(),
         palette::PaletteType::Color2Mod => palette::new_bw(),
-    };
-}
-
-fn new_fractal(w: u32, h: u32) -> mandelbrot::Data {
-    let state = State {
-        width: w,
-        height: h,
-        max_iterations: 500,
-        scale: 2.0,
-        center: Complex64::new(0., 0.),
-        fractal_type: FractalType::Mandelbrot,
-    };
-    mandelbrot::Data::new(state)
+    }
 }
 
 
 struct FractalImage {
+    /// The view asked for, which the tiles of the render are snapped from.
+    request: mandelbrot::State,
     state: mandelbrot::State,
     palette: palette::PaletteType,
     texture: TextureHandle,
+    data: Arc<mandelbrot::Data>,
+    supersamples: Arc<Supersamples>,
 }
 
-fn render_image_linear(fractal: &mandelbrot::Data, buffer: &mut [Color32], pal: &palette::PaletteData) {
-    let mut offset = 0;
-    let scale_factor = (pal.len() - 1) as f64 / fractal.state.max_iterations as f64;
-    fractal.fractal_data.iter().for_each(|row| {
-        row.iter().for_each(|entry| {
-            if entry.escape >= fractal.state.max_iterations {
-                buffer[offset] = Color32::BLACK;
-            } else {
-                let val = (entry.escape as f64 * scale_factor) as usize;
-                buffer[offset] = pal[val];
-            }
-            offset += 1;
+/// What the background thread sends the UI about a render.
+enum RenderUpdate {
+    /// A render of state has started, its tiles are drawn into texture as
+    /// they finish.
+    Started { state: mandelbrot::State, texture: TextureHandle },
+    Finished(FractalImage),
+}
+
+/// Number of significant decimal places needed to tell neighbouring pixels
+/// of state apart.
+fn coordinate_precision(state: &mandelbrot::State) -> usize {
+    let pixel_log = bigcoord::log10(&state.scale) - (state.width as f64).log10();
+    (-pixel_log).ceil().max(0.0) as usize + 2
+}
+
+/// A point written as `re +imi` with places decimal places.
+fn format_point(point: &BigComplex, places: usize) -> String {
+    let round = |x: &BigDecimal| x.with_scale_round(places as i64, RoundingMode::HalfEven).to_plain_string();
+    let im = round(&point.im);
+    match im.strip_prefix('-') {
+        Some(abs) => format!("{} -{}i", round(&point.re), abs),
+        None => format!("{} +{}i", round(&point.re), im),
+    }
+}
+
+/// The sample of fractal at point, if it lies inside the data. The pixel is
+/// found from the offset to the center of the data, so it is right at any
+/// depth.
+fn sample_at(fractal: &mandelbrot::Data, point: &BigComplex) -> Option<mandelbrot::FractalSample> {
+    let (x, y) = fractal.state.offset_to_pixel(point.difference(&fractal.state.center));
+    let (x, y) = (x.floor(), y.floor());
+    if x < 0.0 || y < 0.0 || x >= fractal.state.width as f64 || y >= fractal.state.height as f64 {
+        return None;
+    }
+    Some(fractal.sample(x as usize, y as usize))
+}
+
+/// Where the texture rendered for texture_state lands when showing view, so
+/// the old image can stand in while a new render is in progress.
+fn texture_placement(view: &mandelbrot::State, texture_state: &mandelbrot::State, rect: egui::Rect, pixels_per_point: f32) -> egui::Rect {
+    if view.fractal_type != texture_state.fractal_type || view.rotation != texture_state.rotation {
+        return rect;
+    }
+    let shift = texture_state.center.difference(&view.center);
+    let top_left = shift + texture_state.pixel_offset(0, 0);
+    let bottom_right = shift + texture_state.pixel_offset(texture_state.width as i32, texture_state.height as i32);
+    let (x0, y0) = view.offset_to_pixel(top_left);
+    let (x1, y1) = view.offset_to_pixel(bottom_right);
+    egui::Rect::from_min_max(
+        rect.min + egui::vec2(x0 as f32, y0 as f32) / pixels_per_point,
+        rect.min + egui::vec2(x1 as f32, y1 as f32) / pixels_per_point,
+    )
+}
+
+/// Whether two states show the same view apart from the render size, so a
+/// re-render after a window resize can replace the view in the history.
+fn same_view_resized(a: &mandelbrot::State, b: &mandelbrot::State) -> bool {
+    (a.width != b.width || a.height != b.height)
+        && a.center == b.center
+        && a.rotation == b.rotation
+        && a.fractal_type == b.fractal_type
+        && a.max_iterations == b.max_iterations
+}
+
+/// The screen rectangle that will fill the view after zooming into the
+/// rectangle dragged between a and b.
+fn zoom_rect_target(a: egui::Pos2, b: egui::Pos2, aspect: f64) -> egui::Rect {
+    let dragged = egui::Rect::from_two_pos(a, b);
+    let width = dragged.width().max(dragged.height() * aspect as f32);
+    egui::Rect::from_center_size(dragged.center(), egui::vec2(width, width / aspect as f32))
+}
+
+/// The whole Julia set for c, at the given render size.
+fn julia_state(c: Complex64, width: u32, height: u32, max_iterations: u32) -> mandelbrot::State {
+    let mut state = mandelbrot::State::new(width, height);
+    state.fractal_type = FractalType::Julia(c);
+    state = state.home();
+    state.max_iterations = max_iterations;
+    state
+}
+
+/// Draw the orbit of a point as connected dots over the image in rect.
+fn draw_orbit(painter: &egui::Painter, state: &mandelbrot::State, orbit: &mandelbrot::Orbit, rect: egui::Rect, pixels_per_point: f32) {
+    let points: Vec<egui::Pos2> = orbit
+        .points
+        .iter()
+        .map(|z| {
+            let (x, y) = state.mandelbrot_coord_to_pixel(*z);
+            // keep far away points representable once converted to f32
+            let x = x.clamp(-1e6, 1e6) as f32;
+            let y = y.clamp(-1e6, 1e6) as f32;
+            rect.min + egui::vec2(x, y) / pixels_per_point
         })
-    });
+        .collect();
+    painter.add(egui::Shape::line(points.clone(), egui::Stroke::new(1.0, Color32::from_white_alpha(160))));
+    for p in points.iter().skip(1) {
+        painter.circle_filled(*p, 2.0, Color32::YELLOW);
+    }
+    painter.circle_stroke(points[0], 4.0, egui::Stroke::new(1.5, Color32::RED));
 }
 
-fn render_image_modulus(
+/// Draw a Julia morph path over the image in rect.
+fn draw_path(painter: &egui::Painter, state: &mandelbrot::State, path: &[Complex64], closed: bool, rect: egui::Rect, pixels_per_point: f32) {
+    let mut points: Vec<egui::Pos2> = path
+        .iter()
+        .map(|c| {
+            let (x, y) = state.mandelbrot_coord_to_pixel(*c);
+            rect.min + egui::vec2(x.clamp(-1e6, 1e6) as f32, y.clamp(-1e6, 1e6) as f32) / pixels_per_point
+        })
+        .collect();
+    if closed {
+        points.push(points[0]);
+    }
+    painter.add(egui::Shape::line(points.clone(), egui::Stroke::new(2.0, Color32::LIGHT_BLUE)));
+    painter.circle_filled(points[0], 3.0, Color32::LIGHT_BLUE);
+}
+
+/// Color the retained data of a render and save it as a PNG in the working
+/// directory, optionally with the overlay drawn into the image.
+fn export_image(
     fractal: &mandelbrot::Data,
-    buffer: &mut [Color32],
-    pal: &palette::PaletteData,
+    supersamples: &Supersamples,
+    pal_type: palette::PaletteType,
+    overlay: Option<OverlayOptions>,
 ) {
-    let mut offset = 0;
-    let pal_len = pal.len();
-    fractal.fractal_data.iter().for_each(|row| {
-        row.iter().for_each(|entry| {
-            if entry.escape >= fractal.state.max_iterations {
-                buffer[offset] = Color32::BLACK;
-            } else {
-                buffer[offset] = pal[entry.escape as usize % pal_len];
+    let mut image = supersample::color(fractal, supersamples, &pal_type.to_palette());
+    if let Some(options) = overlay {
+        overlay::bake(&mut image, &overlay::marks(&fractal.state, &options));
+    }
+    let path = export::next_file_name("fractal", "png");
+    match export::save_png(&path, &image) {
+        Ok(()) => println!("saved {}", path.display()),
+        Err(err) => println!("failed to save {}: {}", path.display(), err),
+    }
+}
+
+/// Save one full turn of the palette over the retained data of a render as
+/// an animated PNG, at speed palette entries per second.
+fn export_palette_cycle(fractal: &mandelbrot::Data, supersamples: &Supersamples, pal_type: palette::PaletteType, speed: f32) {
+    let len = pal_type.to_palette().palette.len();
+    // the frames are spread evenly around the palette, so the cycle loops
+    // without a jump
+    let frames = (len as f32 * CYCLE_EXPORT_FPS as f32 / speed.abs().max(1.0)).round().clamp(1.0, len as f32) as u32;
+    let (width, height) = (fractal.state.width, fractal.state.height);
+    let path = export::next_file_name("cycle", "png");
+    let result = export::save_apng(&path, width, height, frames, CYCLE_EXPORT_FPS, &mut |i| {
+        let step = (i as usize * len) / frames as usize;
+        let offset = if speed < 0.0 { len - step } else { step };
+        supersample::color(fractal, supersamples, &pal_type.to_palette_with_offset(offset))
+    });
+    match result {
+        Ok(()) => println!("saved {}", path.display()),
+        Err(err) => println!("failed to save {}: {}", path.display(), err),
+    }
+}
+
+/// An animation being rendered to disk by a background thread.
+struct AnimationRender {
+    dir: PathBuf,
+    frames: u32,
+    done: Arc<AtomicU32>,
+    /// How the render ended, once it has.
+    outcome: Arc<Mutex<Option<Result<(), String>>>>,
+}
+
+impl AnimationRender {
+    /// Render the animation on a new thread, repainting the UI after every
+    /// frame so the progress shown stays current.
+    fn spawn(animation: Box<dyn animation::Animation + Send>, prefix: &str, supersampling: Supersampling, ctx: &Context) -> Self {
+        let dir = export::next_file_name(prefix, "");
+        let done = Arc::new(AtomicU32::new(0));
+        let outcome = Arc::new(Mutex::new(None));
+        let render = Self {
+            dir: dir.clone(),
+            frames: animation.frame_count(),
+            done: done.clone(),
+            outcome: outcome.clone(),
+        };
+        let ctx = ctx.clone();
+        thread::spawn(move || {
+            let result = animation::render_frames(animation.as_ref(), &dir, &supersampling, &mut |n| {
+                done.store(n, Ordering::Relaxed);
+                ctx.request_repaint();
+            });
+            match &result {
+                Ok(()) => println!("rendered frames to {}", dir.display()),
+                Err(err) => println!("failed rendering frames to {}: {}", dir.display(), err),
             }
-            offset += 1;
+            *outcome.lock().unwrap() = Some(result.map_err(|err| err.to_string()));
+            ctx.request_repaint();
         });
-    });
+        render
+    }
+
+    fn label(&self) -> String {
+        let done = self.done.load(Ordering::Relaxed);
+        match self.outcome.lock().unwrap().as_ref() {
+            Some(Ok(())) => format!("Wrote {} frames to {}", self.frames, self.dir.display()),
+            Some(Err(err)) => format!("Failed after {} frames: {}", done, err),
+            None => format!("Rendering frame {}/{}", (done + 1).min(self.frames), self.frames),
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.outcome.lock().unwrap().is_some()
+    }
 }
 
-fn render_image_to_surface(
-    fractal: &mandelbrot::Data,
-    image: &mut ColorImage,
-    pal: &palette::Palette,
-) {
-    match pal.color_mode {
-        palette::ColorMode::LinearScale => render_image_linear(fractal, image.pixels.as_mut_slice(), &pal.palette),
-        palette::ColorMode::Modulus => render_image_modulus(fractal, image.pixels.as_mut_slice(), &pal.palette),
-    };
+/// The center and scale as written in the Controls window, so they can be
+/// edited to any number of digits.
+#[derive(Default)]
+struct ViewText {
+    re: String,
+    im: String,
+    scale: String,
+}
+
+impl ViewText {
+    fn from_state(state: &mandelbrot::State) -> Self {
+        Self {
+            re: state.center.re.to_string(),
+            im: state.center.im.to_string(),
+            scale: state.scale.to_string(),
+        }
+    }
+
+    /// Set the center and scale of state from the text.
+    fn apply(&self, state: &mut mandelbrot::State) -> Result<(), String> {
+        let parse = |name: &str, text: &str| {
+            text.trim().parse::<BigDecimal>().map_err(|_| format!("bad value for {}: `{}`", name, text))
+        };
+        let center = BigComplex {
+            re: parse("center re", &self.re)?,
+            im: parse("center im", &self.im)?,
+        };
+        let scale = params::parse_scale(&self.scale).ok_or_else(|| {
+            format!("scale must be a positive number no smaller than {:e}, found `{}`", mandelbrot::MIN_SCALE, self.scale)
+        })?;
+        (state.center, state.scale) = (center, scale);
+        Ok(())
+    }
 }
 
 struct StateAndPalette {
     state: mandelbrot::State,
     pal: palette::PaletteType,
+    supersampling: Supersampling,
 }
 
 impl StateAndPalette {
-    pub fn new(state: mandelbrot::State, pal: palette::PaletteType) -> Self {
+    pub fn new(state: mandelbrot::State, pal: palette::PaletteType, supersampling: Supersampling) -> Self {
         Self{
             state,
             pal,
+            supersampling,
         }
     }
 }
@@ -109,94 +358,561 @@ impl StateAndPalette {
 struct FractalViewer {
     current_state: mandelbrot::State,
     current_texture: Option<TextureHandle>,
+    texture_state: mandelbrot::State,
+    /// The escape data of the last render, kept for the cursor readout.
+    current_data: Option<Arc<mandelbrot::Data>>,
+    /// The extra samples of the last render, so it can be recolored.
+    current_supersamples: Option<Arc<Supersamples>>,
     current_palette: palette::PaletteType,
-    ui_recv: Receiver<FractalImage>,
+    supersampling: Supersampling,
+    zoom_factor: f64,
+    zoom_rect: Option<(egui::Pos2, egui::Pos2)>,
+    /// The pixels per point the size of current_state was chosen for.
+    render_pixels_per_point: f32,
+    /// The point in the complex plane last under the mouse.
+    hover_coord: Option<Complex64>,
+    /// hover_coord to the precision of the view, for the status bar.
+    hover_point: Option<BigComplex>,
+    view_text: ViewText,
+    /// Whether a field of view_text has focus, so it is not overwritten.
+    editing_view: bool,
+    /// Why the view text last failed to apply, shown until it is fixed.
+    view_text_error: Option<String>,
+    show_julia_preview: bool,
+    show_orbit: bool,
+    show_minimap: bool,
+    /// The overview of the whole set shown in the minimap, and the palette
+    /// it was colored with.
+    minimap: Option<(mandelbrot::State, palette::PaletteType, TextureHandle)>,
+    overlay: OverlayOptions,
+    bake_overlay: bool,
+    zoom_start: Option<mandelbrot::State>,
+    zoom_end: Option<mandelbrot::State>,
+    zoom_frames: u32,
+    keyframes: Vec<animation::Keyframe>,
+    /// The path the Julia constant follows in a morph, and whether it loops.
+    morph_path: Vec<Complex64>,
+    morph_closed: bool,
+    /// Whether dragging over the Mandelbrot set draws the morph path instead
+    /// of panning.
+    drawing_path: bool,
+    morph_frames: u32,
+    morph_playing: bool,
+    /// The frame of the morph shown live, and its texture.
+    morph_preview: Option<(u32, TextureHandle)>,
+    cycling: bool,
+    /// How fast the palette cycles, in palette entries per second.
+    cycle_speed: f32,
+    cycle_offset: f64,
+    /// The current render recolored with the cycled palette. It is kept
+    /// apart from current_texture, which the history shares.
+    cycle_texture: Option<TextureHandle>,
+    animation_render: Option<AnimationRender>,
+    /// A point whose orbit is shown instead of the one under the mouse.
+    pinned_orbit: Option<Complex64>,
+    julia_preview: Option<(Complex64, TextureHandle)>,
+    history: History,
+    keymap: Keymap,
+    /// The render in progress and its partly drawn texture.
+    rendering: Option<(mandelbrot::State, TextureHandle)>,
+    /// How far the background thread is with the current render.
+    progress: Arc<Progress>,
+    ui_recv: Receiver<RenderUpdate>,
     ui_send: Sender<Option<StateAndPalette>>
 }
 
 impl FractalViewer {
     fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        let mut state = mandelbrot::State::new(WIDTH, HEIGHT);
-        state.center.re -= 0.5;
+        let state = mandelbrot::State::new(WIDTH, HEIGHT).home();
 
-        let (background_send, ui_recv) = channel::<FractalImage>();
+        let (background_send, ui_recv) = channel::<RenderUpdate>();
         let (ui_send, background_recv) = channel::<Option<StateAndPalette>>();
         let background_cc = cc.egui_ctx.clone();
-        let thread_handle = thread::spawn(move || {
-            background_thread(background_cc, background_recv, background_send);
+        let progress = Arc::new(Progress::new());
+        let background_progress = progress.clone();
+        thread::spawn(move || {
+            background_thread(background_cc, background_recv, background_send, background_progress);
         });
 
-        ui_send.send(Some(StateAndPalette::new(state.clone(), palette::PaletteType::Color1Lin))).unwrap();
+        ui_send.send(Some(StateAndPalette::new(state.clone(), palette::PaletteType::Color1Lin, Supersampling::OFF))).unwrap();
         FractalViewer{
-            current_state: state,
+            current_state: state.clone(),
             current_texture: None,
+            texture_state: state,
+            current_data: None,
+            current_supersamples: None,
             current_palette: palette::PaletteType::Color1Lin,
+            supersampling: Supersampling::OFF,
+            zoom_factor: 1.25,
+            zoom_rect: None,
+            render_pixels_per_point: 1.0,
+            hover_coord: None,
+            hover_point: None,
+            view_text: ViewText::default(),
+            editing_view: false,
+            view_text_error: None,
+            show_julia_preview: true,
+            show_orbit: false,
+            show_minimap: true,
+            minimap: None,
+            overlay: OverlayOptions::default(),
+            bake_overlay: false,
+            zoom_start: None,
+            zoom_end: None,
+            zoom_frames: 100,
+            keyframes: Vec::new(),
+            morph_path: Vec::new(),
+            morph_closed: false,
+            drawing_path: false,
+            morph_frames: 100,
+            morph_playing: false,
+            morph_preview: None,
+            cycling: false,
+            cycle_speed: 50.0,
+            cycle_offset: 0.0,
+            cycle_texture: None,
+            animation_render: None,
+            pinned_orbit: None,
+            julia_preview: None,
+            history: History::new(),
+            keymap: Keymap::load(),
+            rendering: None,
+            progress,
             ui_recv,
             ui_send,
         }
     }
+
+    /// Show the current history entry, re-rendering it if its texture is no
+    /// longer cached.
+    fn show_history_entry(&mut self) {
+        let entry = match self.history.current() {
+            Some(entry) => entry,
+            None => return,
+        };
+        self.current_state = entry.state.clone();
+        self.current_palette = entry.palette;
+        match entry.texture.as_ref() {
+            Some(texture) => {
+                self.current_texture = Some(texture.clone());
+                self.texture_state = entry.state.clone();
+            }
+            None => self.ui_send.send(Some(StateAndPalette::new(entry.state.clone(), entry.palette, self.supersampling))).unwrap(),
+        }
+    }
+
+    /// Re-render the Julia preview when the point under the mouse has moved.
+    fn update_julia_preview(&mut self, ctx: &Context) {
+        let c = match self.hover_coord {
+            Some(c) => c,
+            None => return,
+        };
+        if let Some((preview_c, _)) = self.julia_preview.as_ref() {
+            if *preview_c == c {
+                return;
+            }
+        }
+        let max_iterations = self.current_state.max_iterations.min(PREVIEW_MAX_ITERATIONS);
+        let mut state = julia_state(c, PREVIEW_WIDTH, PREVIEW_HEIGHT, max_iterations);
+        state.precision = Precision::F32;
+        let image = render_to_image(state, &self.current_palette.to_palette());
+        match self.julia_preview.as_mut() {
+            Some((preview_c, texture)) => {
+                texture.set(image, Default::default());
+                *preview_c = c;
+            }
+            None => self.julia_preview = Some((c, ctx.load_texture("julia_preview", image, Default::default()))),
+        }
+    }
+
+    /// Render the overview of the whole set for the current fractal type if
+    /// the cached one is out of date.
+    fn update_minimap(&mut self, ctx: &Context) {
+        let mut state = mandelbrot::State::new(MINIMAP_WIDTH, MINIMAP_HEIGHT).home();
+        state.fractal_type = self.current_state.fractal_type.clone();
+        state.max_iterations = PREVIEW_MAX_ITERATIONS;
+        if let FractalType::Julia(c) = state.fractal_type {
+            state = julia_state(c, MINIMAP_WIDTH, MINIMAP_HEIGHT, PREVIEW_MAX_ITERATIONS);
+        }
+        state.precision = Precision::F32;
+        if let Some((minimap_state, pal, _)) = self.minimap.as_ref() {
+            if *minimap_state == state && *pal == self.current_palette {
+                return;
+            }
+        }
+        let image = render_to_image(state.clone(), &self.current_palette.to_palette());
+        let texture = ctx.load_texture("minimap", image, Default::default());
+        self.minimap = Some((state, self.current_palette, texture));
+    }
+
+    /// The morph along the current path, with views of the given size.
+    fn julia_morph(&self, width: u32, height: u32, max_iterations: u32) -> animation::JuliaMorph {
+        animation::JuliaMorph {
+            view: julia_state(self.morph_path[0], width, height, max_iterations),
+            path: self.morph_path.clone(),
+            closed: self.morph_closed,
+            frames: self.morph_frames,
+            palette: self.current_palette,
+        }
+    }
+
+    /// Show the next frame of the morph, looping at the end.
+    fn update_morph_preview(&mut self, ctx: &Context) {
+        let max_iterations = self.current_state.max_iterations.min(PREVIEW_MAX_ITERATIONS);
+        let mut morph = self.julia_morph(MORPH_WIDTH, MORPH_HEIGHT, max_iterations);
+        morph.view.precision = Precision::F32;
+        let frame = match self.morph_preview.as_ref() {
+            Some((frame, _)) => (frame + 1) % morph.frames,
+            None => 0,
+        };
+        let (state, pal) = animation::Animation::frame(&morph, frame);
+        let image = render_to_image(state, &pal);
+        match self.morph_preview.as_mut() {
+            Some((preview_frame, texture)) => {
+                texture.set(image, Default::default());
+                *preview_frame = frame;
+            }
+            None => self.morph_preview = Some((frame, ctx.load_texture("morph_preview", image, Default::default()))),
+        }
+        ctx.request_repaint();
+    }
+
+    /// Advance the palette cycle and recolor the current render with it,
+    /// without computing the fractal again.
+    fn update_palette_cycle(&mut self, ctx: &Context) {
+        let (data, supersamples) = match (self.current_data.as_ref(), self.current_supersamples.as_ref()) {
+            (Some(data), Some(supersamples)) => (data, supersamples),
+            _ => return,
+        };
+        let len = self.current_palette.to_palette().palette.len() as f64;
+        let dt = ctx.input(|i| i.stable_dt) as f64;
+        self.cycle_offset = (self.cycle_offset + self.cycle_speed as f64 * dt).rem_euclid(len);
+        let pal = self.current_palette.to_palette_with_offset(self.cycle_offset as usize);
+        let image = supersample::color(data, supersamples, &pal);
+        match self.cycle_texture.as_mut() {
+            Some(texture) => texture.set(image, Default::default()),
+            None => self.cycle_texture = Some(ctx.load_texture("palette_cycle", image, Default::default())),
+        }
+        ctx.request_repaint();
+    }
+
+    fn history_back(&mut self) {
+        if self.history.back().is_some() {
+            self.show_history_entry();
+        }
+    }
+
+    fn history_forward(&mut self) {
+        if self.history.forward().is_some() {
+            self.show_history_entry();
+        }
+    }
 }
 
 impl App for FractalViewer {
-    fn update(&mut self, ctx: &Context, frame: &mut Frame) {
-        let mut new_palette = self.current_palette.clone();
-        if let Ok(new_image) = self.ui_recv.try_recv() {
-            self.current_state = new_image.state;
-            self.current_palette = new_image.palette.clone();
-            new_palette = self.current_palette.clone();
+    fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+        while let Ok(update) = self.ui_recv.try_recv() {
+            let new_image = match update {
+                RenderUpdate::Started { state, texture } => {
+                    self.rendering = Some((state, texture));
+                    continue;
+                }
+                RenderUpdate::Finished(new_image) => new_image,
+            };
+            self.rendering = None;
+            // the view has moved on since this was asked for, so it is not
+            // an entry of the history and would only cut off its forward part
+            if new_image.request != self.current_state {
+                continue;
+            }
+            // take on the snapped view, so the render is shown pixel for pixel
+            self.current_state = new_image.state.clone();
+            let is_current = self
+                .history
+                .current()
+                .map(|e| e.state == new_image.state && e.palette == new_image.palette)
+                .unwrap_or(false);
+            let is_resize = self
+                .history
+                .current()
+                .map(|e| e.palette == new_image.palette && same_view_resized(&e.state, &new_image.state))
+                .unwrap_or(false);
+            if is_current {
+                self.history.set_current_texture(new_image.texture.clone());
+            } else if is_resize {
+                self.history.replace_current(new_image.state.clone(), new_image.palette, new_image.texture.clone());
+            } else {
+                self.history.push(new_image.state.clone(), new_image.palette, new_image.texture.clone());
+            }
+            self.texture_state = new_image.state;
             self.current_texture = Some(new_image.texture);
+            self.current_data = Some(new_image.data);
+            self.current_supersamples = Some(new_image.supersamples);
         }
 
-        let mut panel = egui::CentralPanel::default();
+        let actions = self.keymap.pressed_actions(ctx);
+        for action in actions.iter() {
+            match action {
+                Action::Back => self.history_back(),
+                Action::Forward => self.history_forward(),
+                _ => {}
+            }
+        }
+
+        let mut new_palette = self.current_palette;
+        egui::TopBottomPanel::bottom("status").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if self.progress.done() < self.progress.total() {
+                    let bar = egui::ProgressBar::new(self.progress.fraction())
+                        .desired_width(PROGRESS_WIDTH)
+                        .text(format!("Rendering {}", self.progress.describe()));
+                    ui.add(bar);
+                    ui.separator();
+                    // the supersampling pass does not ask for repaints, keep
+                    // the bar moving anyway
+                    ctx.request_repaint_after(PROGRESS_REPAINT);
+                }
+                let point = match self.hover_point.as_ref() {
+                    Some(point) => point,
+                    None => return,
+                };
+                let precision = coordinate_precision(&self.current_state);
+                ui.monospace(format_point(point, precision));
+                let sample = self
+                    .current_data
+                    .as_ref()
+                    .filter(|data| data.state.fractal_type == self.current_state.fractal_type)
+                    .and_then(|data| sample_at(data, point).map(|sample| (sample, data.state.max_iterations)));
+                if let Some((sample, max_iterations)) = sample {
+                    ui.separator();
+                    if sample.escape >= max_iterations {
+                        ui.monospace(format!("inside ({} iterations)", max_iterations));
+                    } else {
+                        ui.monospace(format!("escape: {}", sample.escape));
+                        ui.separator();
+                        ui.monospace(format!("smooth: {:.3}", sample.smooth()));
+                    }
+                    ui.separator();
+                    ui.monospace(format!("z: {:.6} {:+.6}i", sample.z.re, sample.z.im));
+                }
+            });
+        });
+
+        let panel = egui::CentralPanel::default().frame(egui::Frame::none());
         let mut new_state = self.current_state.clone();
 
         let mut send_new_state = false;
+        let mut view_moved = false;
+        let mut orbit = None;
+        for action in actions {
+            let (width, height) = (new_state.width as f64, new_state.height as f64);
+            match action {
+                Action::PanLeft => new_state.pan_pixels(width * PAN_FRACTION, 0.0),
+                Action::PanRight => new_state.pan_pixels(-width * PAN_FRACTION, 0.0),
+                Action::PanUp => new_state.pan_pixels(0.0, height * PAN_FRACTION),
+                Action::PanDown => new_state.pan_pixels(0.0, -height * PAN_FRACTION),
+                Action::ZoomIn => new_state.zoom(1.0 / self.zoom_factor),
+                Action::ZoomOut => new_state.zoom(self.zoom_factor),
+                Action::MoreIterations => new_state.max_iterations += ITERATION_STEP,
+                Action::FewerIterations => {
+                    new_state.max_iterations = new_state.max_iterations.saturating_sub(ITERATION_STEP).max(ITERATION_STEP)
+                }
+                Action::CyclePalette => new_palette = cycle_palette(new_palette.to_palette()).palette_type,
+                Action::ToggleFractal => {
+                    new_state.fractal_type = match new_state.fractal_type {
+                        FractalType::Mandelbrot => FractalType::Julia(new_state.center_f64()),
+                        FractalType::Julia(_) => FractalType::Mandelbrot,
+                    }
+                }
+                Action::ResetView => new_state = new_state.home(),
+                Action::Back | Action::Forward => continue,
+            }
+            send_new_state = true;
+        }
         panel.show(ctx, |ui| {
-            if let Some(texture) = self.current_texture.as_ref() {
-                let img = egui::Image::new((texture.id(), texture.size_vec2())).sense(Sense::click());
-                let mut img_resp = ui.add(img);
-                if img_resp.clicked() {
+            let ppp = ctx.pixels_per_point();
+            let size = ui.available_size();
+            let width = (size.x * ppp).round() as u32;
+            let height = (size.y * ppp).round() as u32;
+            if width > 0 && height > 0 && (width != new_state.width || height != new_state.height) {
+                // keep the size of a point in the complex plane, so resizing
+                // reveals more or less of the set rather than stretching it
+                let old_width = new_state.width as f64 / self.render_pixels_per_point as f64;
+                new_state.zoom(size.x as f64 / old_width);
+                new_state.width = width;
+                new_state.height = height;
+                self.render_pixels_per_point = ppp;
+                send_new_state = true;
+            }
+            let (rect, img_resp) = ui.allocate_exact_size(size, Sense::click_and_drag());
+            let to_pixel = |pos: egui::Pos2| {
+                let p = (pos - rect.min) * ppp;
+                (p.x as i32, p.y as i32)
+            };
+            if let Some(pos) = img_resp.hover_pos() {
+                let (x, y) = to_pixel(pos);
+                self.hover_coord = Some(new_state.pixel_to_mandelbrot_coord(x, y));
+                self.hover_point = Some(new_state.pixel_point(x, y));
+            }
+            if img_resp.middle_clicked() {
+                self.pinned_orbit = match self.pinned_orbit {
+                    Some(_) => None,
+                    None => self.hover_coord,
+                };
+            }
+            if img_resp.clicked() {
 
-                    let pos = img_resp.hover_pos().unwrap();
-                    println!("clicked at {:?} rect is {:?}", &pos, &img_resp.rect);
+                let pos = img_resp.interact_pointer_pos().unwrap();
+                println!("clicked at {:?} rect is {:?}", &pos, &img_resp.rect);
 
-                    let x = (pos.x - img_resp.rect.left()) as i32;
-                    let y = (pos.y - img_resp.rect.top()) as i32;
-                    new_state.center = new_state.pixel_to_mandelbrot_coord(x, y);
+                let (x, y) = to_pixel(pos);
+                new_state.center = new_state.pixel_point(x, y);
+                send_new_state = true;
+            }
+            let drawing_path = self.drawing_path && new_state.fractal_type == FractalType::Mandelbrot;
+            if drawing_path && img_resp.drag_started() {
+                self.morph_path.clear();
+                self.morph_closed = false;
+                self.morph_preview = None;
+            }
+            if drawing_path && img_resp.dragged() {
+                if let Some(pos) = img_resp.interact_pointer_pos() {
+                    let (x, y) = to_pixel(pos);
+                    let c = new_state.pixel_to_mandelbrot_coord(x, y);
+                    let far_enough = self.morph_path.last().map(|last| {
+                        let (lx, ly) = new_state.mandelbrot_coord_to_pixel(*last);
+                        egui::vec2(lx as f32 - x as f32, ly as f32 - y as f32).length() >= MORPH_PATH_STEP * ppp
+                    });
+                    if far_enough.unwrap_or(true) {
+                        self.morph_path.push(c);
+                    }
+                }
+            } else if img_resp.drag_started() {
+                let rect_zoom = ui.input(|i| i.modifiers.shift || i.pointer.secondary_down());
+                self.zoom_rect = match (rect_zoom, ui.input(|i| i.pointer.press_origin())) {
+                    (true, Some(origin)) => Some((origin, origin)),
+                    _ => None,
+                };
+            }
+            if img_resp.dragged() && !drawing_path {
+                if let Some((start, _)) = self.zoom_rect {
+                    if let Some(pos) = img_resp.interact_pointer_pos() {
+                        self.zoom_rect = Some((start, pos));
+                    }
+                } else {
+                    let delta = img_resp.drag_delta() * ppp;
+                    new_state.pan_pixels(delta.x as f64, delta.y as f64);
+                    view_moved = true;
+                }
+            }
+            if img_resp.drag_released() && !drawing_path {
+                if let Some((start, end)) = self.zoom_rect.take() {
+                    let dragged = egui::Rect::from_two_pos(start, end);
+                    if dragged.width() >= MIN_ZOOM_RECT || dragged.height() >= MIN_ZOOM_RECT {
+                        let (x0, y0) = to_pixel(start);
+                        let (x1, y1) = to_pixel(end);
+                        new_state.zoom_to_rect(x0, y0, x1, y1);
+                        send_new_state = true;
+                    }
+                } else {
+                    send_new_state = true;
+                }
+            }
+            if let Some(pos) = img_resp.hover_pos() {
+                let scroll = ui.input(|i| i.raw_scroll_delta.y);
+                if scroll != 0.0 {
+                    let (x, y) = to_pixel(pos);
+                    let factor = self.zoom_factor.powf(-(scroll / SCROLL_NOTCH) as f64);
+                    new_state.zoom_at(x, y, factor);
                     send_new_state = true;
                 }
+            }
 
+            if self.cycling {
+                self.update_palette_cycle(ctx);
+            }
+            // the recolored render stands in for the texture, as long as it
+            // was made from the same data
+            let cycled = match (self.cycling, self.cycle_texture.as_ref(), self.current_data.as_ref()) {
+                (true, Some(texture), Some(data)) if data.state == self.texture_state => Some(texture),
+                _ => None,
+            };
+            if let Some(texture) = cycled.or(self.current_texture.as_ref()) {
+                let placement = texture_placement(&new_state, &self.texture_state, rect, ppp);
+                let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+                ui.painter_at(rect).image(texture.id(), placement, uv, Color32::WHITE);
+            }
+            // the tiles finished so far, over the last render
+            if let Some((state, texture)) = self.rendering.as_ref() {
+                let placement = texture_placement(&new_state, state, rect, ppp);
+                let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+                ui.painter_at(rect).image(texture.id(), placement, uv, Color32::WHITE);
+            }
+            if self.overlay.any() {
+                overlay::paint(&ui.painter_at(rect), &overlay::marks(&new_state, &self.overlay), rect, ppp);
+            }
+            if self.morph_path.len() >= 2 && new_state.fractal_type == FractalType::Mandelbrot {
+                draw_path(&ui.painter_at(rect), &new_state, &self.morph_path, self.morph_closed, rect, ppp);
+            }
+            if self.show_orbit {
+                if let Some(point) = self.pinned_orbit.or(self.hover_coord) {
+                    let point_orbit = new_state.orbit_at(point);
+                    draw_orbit(&ui.painter_at(rect), &new_state, &point_orbit, rect, ppp);
+                    orbit = Some(point_orbit);
+                }
+            }
+            if let Some((start, end)) = self.zoom_rect {
+                let painter = ui.painter_at(rect);
+                painter.rect_stroke(egui::Rect::from_two_pos(start, end), 0.0, egui::Stroke::new(1.0, Color32::from_white_alpha(128)));
+                painter.rect_stroke(zoom_rect_target(start, end, new_state.aspect()), 0.0, egui::Stroke::new(1.5, Color32::WHITE));
             }
         });
+        let mut history_back = false;
+        let mut history_forward = false;
         egui::Window::new("Controls")
             .collapsible(true)
             .resizable(false)
             .show(ctx, |ui| {
                 ui.vertical(|ui| {
+                    ui.horizontal(|ui| {
+                        if ui.add_enabled(self.history.can_go_back(), egui::Button::new("<")).clicked() {
+                            history_back = true;
+                        }
+                        if ui.add_enabled(self.history.can_go_forward(), egui::Button::new(">")).clicked() {
+                            history_forward = true;
+                        }
+                        ui.label(format!("History {}/{}", self.history.position() + 1, self.history.len()));
+                    });
                     ui.horizontal(|ui| {
                         if ui.button("+").clicked() {
-                            new_state.scale *= 0.9;
+                            new_state.zoom(1.0 / self.zoom_factor);
                             send_new_state = true;
                         }
                         if ui.button("-").clicked() {
-                            new_state.scale *= 1.1;
+                            new_state.zoom(self.zoom_factor);
                             send_new_state = true;
                         }
                         ui.label("Zoom")
                     });
-                    ui.horizontal((|ui| {
+                    ui.add(egui::Slider::new(&mut self.zoom_factor, 1.01..=4.0).logarithmic(true).text("Zoom factor"));
+                    let mut degrees = new_state.rotation.to_degrees();
+                    if ui.add(egui::Slider::new(&mut degrees, -180.0..=180.0).suffix("°").text("Rotation")).changed() {
+                        new_state.rotation = degrees.to_radians();
+                        send_new_state = true;
+                    }
+                    ui.horizontal(|ui| {
                         egui::ComboBox::from_label("Fractal Type")
                             .selected_text(format!("{}", self.current_state.fractal_type))
                             .show_ui(ui, |ui| {
                                 ui.selectable_value(&mut new_state.fractal_type, FractalType::Mandelbrot, format!("{}", FractalType::Mandelbrot));
                                 let julia = match new_state.fractal_type {
-                                    FractalType::Mandelbrot => FractalType::Julia(self.current_state.center),
+                                    FractalType::Mandelbrot => FractalType::Julia(self.current_state.center_f64()),
                                     FractalType::Julia(c) => FractalType::Julia(c),
                                 };
                                 ui.selectable_value(&mut new_state.fractal_type, julia.clone(), format!("{}", julia));
                             })
-                    }));
-                    ui.horizontal((|ui| {
+                    });
+                    ui.horizontal(|ui| {
                         egui::ComboBox::from_label("Palette")
                             .selected_text(format!("{:?}", new_palette))
                             .show_ui(ui, |ui| {
@@ -206,27 +922,375 @@ impl App for FractalViewer {
                                 ui.selectable_value(&mut new_palette, palette::PaletteType::Color1Mod, format!("{:?}", palette::PaletteType::Color2Mod));
                                 ui.selectable_value(&mut new_palette, palette::PaletteType::Color2Mod, format!("{:?}", palette::PaletteType::Color2Mod));
                             })
-                    }));
-                    ui.horizontal((|ui| {
+                    });
+                    ui.horizontal(|ui| {
+                        let selected = new_state.precision;
+                        egui::ComboBox::from_label("Precision")
+                            .selected_text(selected.name())
+                            .show_ui(ui, |ui| {
+                                for precision in Precision::ALL {
+                                    ui.selectable_value(&mut new_state.precision, precision, precision.name());
+                                }
+                            });
+                        if new_state.precision != selected {
+                            send_new_state = true;
+                        }
+                    });
+                    let supersampling = self.supersampling;
+                    ui.horizontal(|ui| {
+                        let off = Supersampling::OFF;
+                        let on = |pattern| Supersampling {
+                            pattern,
+                            factor: supersampling.factor.max(2),
+                            ..supersampling
+                        };
+                        let name = |s: Supersampling| match (s.is_off(), s.pattern) {
+                            (true, _) => "Off",
+                            (false, Pattern::Grid) => "Grid",
+                            (false, Pattern::Jitter) => "Jitter",
+                        };
+                        egui::ComboBox::from_label("Antialiasing")
+                            .selected_text(name(supersampling))
+                            .show_ui(ui, |ui| {
+                                for choice in [off, on(Pattern::Grid), on(Pattern::Jitter)] {
+                                    ui.selectable_value(&mut self.supersampling, choice, name(choice));
+                                }
+                            });
+                    });
+                    if !self.supersampling.is_off() {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Slider::new(&mut self.supersampling.factor, 2..=supersample::MAX_FACTOR).text("Samples"));
+                            ui.checkbox(&mut self.supersampling.adaptive, "Edges only")
+                                .on_hover_text("Only supersample pixels whose neighbours differ");
+                        });
+                    }
+                    if self.supersampling != supersampling {
+                        send_new_state = true;
+                    }
+                    ui.horizontal(|ui| {
                         if ui.button("+").clicked() {
-                            new_state.max_iterations += 50;
+                            new_state.max_iterations += ITERATION_STEP;
                             send_new_state = true;
                         }
                         if ui.button("-").clicked() {
-                            new_state.max_iterations -= 50;
+                            new_state.max_iterations = new_state.max_iterations.saturating_sub(ITERATION_STEP).max(ITERATION_STEP);
                             send_new_state = true;
                         }
                         ui.label("Detail");
-                    }));
-                    ui.label(format!("Scale: {}", self.current_state.scale));
+                    });
+                    if !self.editing_view {
+                        self.view_text = ViewText::from_state(&self.current_state);
+                    }
+                    let (mut editing, mut submitted) = (false, false);
+                    egui::Grid::new("view_text").num_columns(2).show(ui, |ui| {
+                        let fields = [
+                            ("Center re", &mut self.view_text.re),
+                            ("Center im", &mut self.view_text.im),
+                            ("Scale", &mut self.view_text.scale),
+                        ];
+                        for (label, text) in fields {
+                            ui.label(label);
+                            let resp = ui.add(egui::TextEdit::singleline(text).desired_width(VIEW_TEXT_WIDTH));
+                            editing |= resp.has_focus();
+                            if resp.changed() {
+                                self.view_text_error = None;
+                            }
+                            submitted |= resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                            ui.end_row();
+                        }
+                    });
+                    if submitted {
+                        match self.view_text.apply(&mut new_state) {
+                            Ok(()) => send_new_state = true,
+                            Err(err) => self.view_text_error = Some(err),
+                        }
+                    }
+                    // keep the rejected text on screen next to its error
+                    self.editing_view = editing || self.view_text_error.is_some();
+                    if let Some(err) = &self.view_text_error {
+                        ui.colored_label(ui.visuals().error_fg_color, err);
+                    }
                     ui.label(format!("Max iter: {}", self.current_state.max_iterations));
-                    ui.label(format!("Center: {}, {}", self.current_state.center.re, self.current_state.center.im));
+                    match self.current_state.precision {
+                        Precision::Auto => ui.label(format!("Precision: {} (auto)", self.current_state.render_precision().name())),
+                        precision => ui.label(format!("Precision: {}", precision.name())),
+                    };
+                    if self.current_state.precision_exhausted() {
+                        ui.colored_label(ui.visuals().warn_fg_color, "Precision exhausted, zooming further shows noise");
+                    }
                     if let FractalType::Julia(c) = self.current_state.fractal_type {
                         ui.label(format!("Julia: {}, {}", c.re, c.im));
                     }
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.show_julia_preview, "Julia preview");
+                        ui.checkbox(&mut self.show_minimap, "Minimap");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.show_orbit, "Orbit");
+                        if self.pinned_orbit.is_some() && ui.button("Unpin").clicked() {
+                            self.pinned_orbit = None;
+                        }
+                    });
+                    if let Some(orbit) = orbit.as_ref() {
+                        if orbit.escaped(new_state.max_iterations) {
+                            ui.label(format!("Escaped after {} iterations", orbit.escape));
+                        } else {
+                            ui.label("Did not escape");
+                        }
+                        ui.label(format!("|z|: {:.6}", orbit.final_z().norm()));
+                        match orbit.period {
+                            Some(period) => ui.label(format!("Period: {}", period)),
+                            None => ui.label("Period: none detected"),
+                        };
+                    }
+                    ui.collapsing("Overlay", |ui| {
+                        ui.checkbox(&mut self.overlay.axes, "Axes");
+                        ui.checkbox(&mut self.overlay.grid, "Grid");
+                        ui.checkbox(&mut self.overlay.scale_bar, "Scale bar");
+                    });
+                    ui.horizontal(|ui| {
+                        let save = ui.add_enabled(self.current_data.is_some(), egui::Button::new("Save PNG"));
+                        if save.clicked() {
+                            if let (Some(data), Some(supersamples)) = (self.current_data.clone(), self.current_supersamples.clone()) {
+                                let pal = self.current_palette;
+                                let overlay = Some(self.overlay).filter(|_| self.bake_overlay);
+                                thread::spawn(move || export_image(&data, &supersamples, pal, overlay));
+                            }
+                        }
+                        ui.checkbox(&mut self.bake_overlay, "Include overlay");
+                    });
+                    ui.collapsing("Palette cycling", |ui| {
+                        ui.checkbox(&mut self.cycling, "Cycle palette");
+                        ui.add(egui::Slider::new(&mut self.cycle_speed, -500.0..=500.0).text("Speed"));
+                        let ready = self.current_data.is_some() && self.cycle_speed != 0.0;
+                        if ui.add_enabled(ready, egui::Button::new("Save APNG")).clicked() {
+                            if let (Some(data), Some(supersamples)) = (self.current_data.clone(), self.current_supersamples.clone()) {
+                                let (pal, speed) = (self.current_palette, self.cycle_speed);
+                                thread::spawn(move || export_palette_cycle(&data, &supersamples, pal, speed));
+                            }
+                        }
+                    });
+                    ui.collapsing("Zoom animation", |ui| {
+                        ui.horizontal(|ui| {
+                            if ui.button("Set start").clicked() {
+                                self.zoom_start = Some(self.current_state.clone());
+                            }
+                            if let Some(start) = self.zoom_start.as_ref() {
+                                ui.label(format!("scale {:e}", start.scale_f64()));
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button("Set end").clicked() {
+                                self.zoom_end = Some(self.current_state.clone());
+                            }
+                            if let Some(end) = self.zoom_end.as_ref() {
+                                ui.label(format!("scale {:e}", end.scale_f64()));
+                            }
+                        });
+                        ui.add(egui::DragValue::new(&mut self.zoom_frames).clamp_range(2..=100000).prefix("Frames: "));
+                        let busy = self.animation_render.as_ref().map(|r| !r.finished()).unwrap_or(false);
+                        let ready = self.zoom_start.is_some() && self.zoom_end.is_some() && !busy;
+                        if ui.add_enabled(ready, egui::Button::new("Render frames")).clicked() {
+                            let zoom = animation::ZoomAnimation {
+                                start: self.zoom_start.clone().unwrap(),
+                                end: self.zoom_end.clone().unwrap(),
+                                frames: self.zoom_frames,
+                                palette: self.current_palette,
+                            };
+                            self.animation_render = Some(AnimationRender::spawn(Box::new(zoom), "zoom", self.supersampling, ctx));
+                        }
+                        if let Some(render) = self.animation_render.as_ref() {
+                            ui.label(render.label());
+                        }
+                    });
+                    ui.collapsing("Keyframes", |ui| {
+                        if ui.button("Add keyframe").clicked() {
+                            self.keyframes.push(animation::Keyframe {
+                                state: self.current_state.clone(),
+                                palette: self.current_palette,
+                                palette_offset: 0.0,
+                                easing: animation::Easing::EaseInOut,
+                                frames: KEYFRAME_FRAMES,
+                            });
+                        }
+                        let mut remove = None;
+                        let count = self.keyframes.len();
+                        egui::Grid::new("keyframes").show(ui, |ui| {
+                            for (i, key) in self.keyframes.iter_mut().enumerate() {
+                                if ui.button(format!("{}", i + 1)).on_hover_text("Go to this keyframe").clicked() {
+                                    new_state = key.state.clone();
+                                    new_palette = key.palette;
+                                    send_new_state = true;
+                                }
+                                ui.label(format!("scale {:.3e}", key.state.scale_f64()));
+                                // the last keyframe ends the animation, so it
+                                // has no frames or easing of its own
+                                if i + 1 < count {
+                                    ui.add(egui::DragValue::new(&mut key.frames).clamp_range(1..=100000).suffix(" frames"));
+                                    egui::ComboBox::from_id_source(("easing", i))
+                                        .selected_text(key.easing.name())
+                                        .show_ui(ui, |ui| {
+                                            for easing in animation::Easing::ALL {
+                                                ui.selectable_value(&mut key.easing, easing, easing.name());
+                                            }
+                                        });
+                                } else {
+                                    ui.label("");
+                                    ui.label("");
+                                }
+                                ui.add(egui::DragValue::new(&mut key.palette_offset).clamp_range(0.0..=1000.0).prefix("offset "));
+                                if ui.button("x").on_hover_text("Remove this keyframe").clicked() {
+                                    remove = Some(i);
+                                }
+                                ui.end_row();
+                            }
+                        });
+                        if let Some(i) = remove {
+                            self.keyframes.remove(i);
+                        }
+                        ui.horizontal(|ui| {
+                            let busy = self.animation_render.as_ref().map(|r| !r.finished()).unwrap_or(false);
+                            let ready = self.keyframes.len() >= 2;
+                            if ui.add_enabled(ready, egui::Button::new("Save keyframes")).clicked() {
+                                let keys = animation::KeyframeAnimation { keys: self.keyframes.clone() };
+                                let path = export::next_file_name("keyframes", "txt");
+                                match std::fs::write(&path, keys.to_params()) {
+                                    Ok(()) => println!("saved {}", path.display()),
+                                    Err(err) => println!("failed to save {}: {}", path.display(), err),
+                                }
+                            }
+                            if ui.add_enabled(ready && !busy, egui::Button::new("Render frames")).clicked() {
+                                let keys = animation::KeyframeAnimation { keys: self.keyframes.clone() };
+                                self.animation_render = Some(AnimationRender::spawn(Box::new(keys), "keyframes", self.supersampling, ctx));
+                            }
+                        });
+                        if let Some(render) = self.animation_render.as_ref() {
+                            ui.label(render.label());
+                        }
+                    });
+                    ui.collapsing("Julia morph", |ui| {
+                        ui.checkbox(&mut self.drawing_path, "Draw path")
+                            .on_hover_text("Drag over the Mandelbrot set to draw the path of the Julia constant");
+                        if ui.button("Circle").on_hover_text("Loop around the center of the view").clicked() {
+                            let radius = self.current_state.scale_f64() / 4.0;
+                            self.morph_path = animation::circle_path(self.current_state.center_f64(), radius, MORPH_CIRCLE_POINTS);
+                            self.morph_closed = true;
+                            self.morph_preview = None;
+                        }
+                        ui.add(egui::DragValue::new(&mut self.morph_frames).clamp_range(2..=100000).prefix("Frames: "));
+                        let ready = self.morph_path.len() >= 2;
+                        ui.horizontal(|ui| {
+                            let play = if self.morph_playing { "Stop" } else { "Play" };
+                            if ui.add_enabled(ready, egui::Button::new(play)).clicked() {
+                                self.morph_playing = !self.morph_playing;
+                            }
+                            let busy = self.animation_render.as_ref().map(|r| !r.finished()).unwrap_or(false);
+                            if ui.add_enabled(ready && !busy, egui::Button::new("Render frames")).clicked() {
+                                let morph = self.julia_morph(self.current_state.width, self.current_state.height, self.current_state.max_iterations);
+                                self.animation_render = Some(AnimationRender::spawn(Box::new(morph), "morph", self.supersampling, ctx));
+                            }
+                        });
+                        if let Some(render) = self.animation_render.as_ref() {
+                            ui.label(render.label());
+                        }
+                    });
+                    ui.collapsing("Keys", |ui| {
+                        egui::Grid::new("keys").show(ui, |ui| {
+                            for action in Keymap::actions() {
+                                ui.label(action.name());
+                                ui.label(self.keymap.describe(ctx, *action));
+                                ui.end_row();
+                            }
+                        });
+                    });
 
                 })
             });
+        if self.show_julia_preview && new_state.fractal_type == FractalType::Mandelbrot {
+            self.update_julia_preview(ctx);
+            if let Some((c, texture)) = self.julia_preview.as_ref() {
+                let c = *c;
+                egui::Window::new("Julia Preview")
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        let img = egui::Image::new((texture.id(), texture.size_vec2())).sense(Sense::click());
+                        if ui.add(img).on_hover_text("Click to open this Julia set").clicked() {
+                            new_state = julia_state(c, new_state.width, new_state.height, new_state.max_iterations);
+                            send_new_state = true;
+                        }
+                        ui.label(format!("c = {:.6} {:+.6}i", c.re, c.im));
+                    });
+            }
+        }
+        if self.morph_path.len() < 2 {
+            self.morph_playing = false;
+        }
+        if self.morph_playing {
+            self.update_morph_preview(ctx);
+        }
+        if self.morph_preview.is_some() {
+            let mut open = true;
+            if let Some((frame, texture)) = self.morph_preview.as_ref() {
+                egui::Window::new("Julia Morph")
+                    .open(&mut open)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        ui.image((texture.id(), texture.size_vec2()));
+                        ui.label(format!("frame {}/{}", frame + 1, self.morph_frames));
+                    });
+            }
+            if !open {
+                self.morph_playing = false;
+                self.morph_preview = None;
+            }
+        }
+        if self.show_minimap {
+            self.update_minimap(ctx);
+            if let Some((minimap_state, _, texture)) = self.minimap.as_ref() {
+                egui::Window::new("Minimap")
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        let img = egui::Image::new((texture.id(), texture.size_vec2())).sense(Sense::click());
+                        let resp = ui.add(img).on_hover_text("Click to move the view here");
+                        let scale = resp.rect.width() / minimap_state.width as f32;
+                        let to_screen = |c: Complex64| {
+                            let (x, y) = minimap_state.mandelbrot_coord_to_pixel(c);
+                            resp.rect.min + egui::vec2(x as f32, y as f32) * scale
+                        };
+                        let (w, h) = (new_state.width as i32, new_state.height as i32);
+                        // the corners in order around the view, which is not
+                        // axis aligned on the minimap when it is rotated
+                        let corners: Vec<egui::Pos2> = [(0, 0), (w, 0), (w, h), (0, h)]
+                            .iter()
+                            .map(|&(x, y)| to_screen(new_state.pixel_to_mandelbrot_coord(x, y)))
+                            .collect();
+                        let painter = ui.painter_at(resp.rect);
+                        let stroke = egui::Stroke::new(1.5, Color32::WHITE);
+                        if corners[0].distance(corners[1]) < MINIMAP_MIN_RECT {
+                            let c = to_screen(new_state.center_f64());
+                            painter.line_segment([c - egui::vec2(6.0, 0.0), c + egui::vec2(6.0, 0.0)], stroke);
+                            painter.line_segment([c - egui::vec2(0.0, 6.0), c + egui::vec2(0.0, 6.0)], stroke);
+                        } else {
+                            painter.add(egui::Shape::closed_line(corners, stroke));
+                        }
+                        if resp.clicked() {
+                            if let Some(pos) = resp.interact_pointer_pos() {
+                                let p = (pos - resp.rect.min) / scale;
+                                new_state.center = minimap_state.pixel_point(p.x as i32, p.y as i32);
+                                send_new_state = true;
+                            }
+                        }
+                    });
+            }
+        }
+        if history_back || history_forward {
+            if history_back {
+                self.history_back();
+            } else {
+                self.history_forward();
+            }
+            return;
+        }
         if new_palette != self.current_palette {
             send_new_state = true;
         }
@@ -235,52 +1299,119 @@ impl App for FractalViewer {
             println!("cur fractal: {0}, new fractal: {1}", self.current_state.fractal_type, new_state.fractal_type);
         }
         if send_new_state {
-            self.ui_send.send(Some(StateAndPalette::new(new_state, new_palette))).unwrap();
+            self.ui_send.send(Some(StateAndPalette::new(new_state.clone(), new_palette, self.supersampling))).unwrap();
+        }
+        if send_new_state || view_moved {
+            self.current_state = new_state;
+            self.current_palette = new_palette;
         }
     }
 }
 
 
 fn main() -> eframe::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(err) = cli::run(&args) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([WIDTH as f32, HEIGHT as f32]).with_resizable(false),
+        viewport: egui::ViewportBuilder::default().with_inner_size([WIDTH as f32, HEIGHT as f32]).with_resizable(true),
         ..Default::default()
     };
 
     eframe::run_native("Fractal Viewer", options, Box::new(|cc| Box::new(FractalViewer::new(cc))))
 }
 
-fn background_thread(ctx: egui::Context, from_ui: Receiver<Option<StateAndPalette>>, out: Sender<FractalImage>) {
+/// The extra samples of fractal, which are those of the last render when it
+/// was of the same view with the same supersampling.
+fn reuse_supersamples(
+    last: &mut Option<Arc<Supersamples>>,
+    fractal: &mandelbrot::Data,
+    supersampling: &Supersampling,
+    progress: &Progress,
+) -> Arc<Supersamples> {
+    match last {
+        Some(extra) if extra.state == fractal.state && extra.supersampling == *supersampling => extra.clone(),
+        _ => last.insert(Arc::new(supersample::supersample(fractal, supersampling, progress))).clone(),
+    }
+}
+
+fn background_thread(
+    ctx: egui::Context,
+    from_ui: Receiver<Option<StateAndPalette>>,
+    out: Sender<RenderUpdate>,
+    progress: Arc<Progress>,
+) {
     println!("background thread started");
+    // tiles of recent renders, so a request for a view that overlaps them,
+    // or the same view in another palette, only computes what is new
+    let mut cache = tiles::TileCache::new();
+    let disk = diskcache::DiskCache::open_default();
+    // the extra samples of the last render, so a request that only changes
+    // the palette recolors them instead of computing them again
+    let mut last_supersamples: Option<Arc<Supersamples>> = None;
     loop {
-        let val = from_ui.recv();
-        let (state, pal_type) = match val {
+        let mut val = from_ui.recv();
+        // only the most recent request matters, skip any that queued up
+        // while the last frame was rendering
+        while let Ok(next) = from_ui.try_recv() {
+            val = Ok(next);
+        }
+        let (request, pal_type, supersampling) = match val {
             Ok(val) => match val {
-                Some(new_state) => (new_state.state, new_state.pal),
+                Some(new_state) => (new_state.state, new_state.pal, new_state.supersampling),
                 None => return,
             },
             Err(_) => return,
         };
         println!("Got state from ui");
-        let mut image = ColorImage::new([state.width as usize, state.height as usize], Color32::BLACK);
         let pal = pal_type.to_palette();
-        let mut fractal = mandelbrot::Data::new(state);
-
         let start = std::time::Instant::now();
-        compute_mandelbrot(&mut fractal);
-        let calc_dur = start.elapsed();
-        let start = std::time::Instant::now();
-        render_image_to_surface(&mut fractal, &mut image, &pal);
-        let render_dur = start.elapsed();
-        let start = std::time::Instant::now();
-        let txt = ctx.load_texture("current", image, Default::default());
-        let load_dur = start.elapsed();
-        println!("calc: {:?}, render: {:?}, load: {:?}", calc_dur, render_dur, load_dur);
-        out.send(FractalImage {
-            state: fractal.state,
+        let (fractal, texture, supersamples) = match cache.plan(&request) {
+            Some(view) => {
+                // the gaps are transparent, so the last render shows through
+                // until every tile is in
+                let blank = ColorImage::new([view.state.width as usize, view.state.height as usize], Color32::TRANSPARENT);
+                let mut texture = ctx.load_texture("current", blank, Default::default());
+                out.send(RenderUpdate::Started {
+                    state: view.state.clone(),
+                    texture: texture.clone(),
+                }).unwrap();
+                let fractal = tiles::render(&mut cache, disk.as_ref(), &view, &progress, &mut |data, rect| {
+                    texture.set_partial([rect[0], rect[1]], render_region(data, rect, &pal), Default::default());
+                    ctx.request_repaint();
+                });
+                // the extra samples are only worth computing once the whole
+                // view is in
+                let supersamples = reuse_supersamples(&mut last_supersamples, &fractal, &supersampling, &progress);
+                if !supersampling.is_off() {
+                    texture.set(supersample::color(&fractal, &supersamples, &pal), Default::default());
+                }
+                (fractal, texture, supersamples)
+            }
+            None => {
+                let mut fractal = mandelbrot::Data::new(request.clone());
+                progress.start(request.height as u64);
+                compute_mandelbrot_with_progress(&mut fractal, &progress);
+                let supersamples = reuse_supersamples(&mut last_supersamples, &fractal, &supersampling, &progress);
+                let image = supersample::color(&fractal, &supersamples, &pal);
+                (fractal, ctx.load_texture("current", image, Default::default()), supersamples)
+            }
+        };
+        println!("render: {:?}", start.elapsed());
+        out.send(RenderUpdate::Finished(FractalImage {
+            request,
+            state: fractal.state.clone(),
             palette: pal_type,
-            texture: txt,
-        }).unwrap();
+            texture,
+            data: Arc::new(fractal),
+            supersamples,
+        })).unwrap();
         ctx.request_repaint();
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,12 @@
 use std::fmt::Display;
 pub use num_complex::{Complex64, ComplexFloat};
+use bigdecimal::BigDecimal;
 use rayon::prelude::*;
+use crate::bigcoord::{self, BigComplex};
 use crate::mandelbrot;
+use crate::precision::{DoubleDouble, QuadDouble, Real};
+use crate::progress::Progress;
+use crate::simd;
 
 #[derive(Clone, PartialEq)]
 pub enum FractalType {
@@ -19,14 +24,77 @@ impl Display for FractalType {
     }
 }
 
-#[derive(Clone)]
+/// How the pixels of a render are laid out over the complex plane.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Sampling {
+    /// An ordinary image of the view.
+    Rectangular,
+    /// Columns go once around the center and rows go inward by the same
+    /// factor every row, starting on a circle of diameter scale. Pixels stay
+    /// square, so a zoom can be rebuilt from the rows at every depth.
+    LogPolar,
+}
+
+/// The floating point type a render is computed with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Precision {
+    /// The cheapest precision of F64 and above that can tell neighbouring
+    /// pixels apart, chosen for each render.
+    Auto,
+    /// Fast but only good for shallow views, such as small previews.
+    F32,
+    F64,
+    /// About 32 significant digits, for zooms to about 1e-28.
+    DoubleDouble,
+    /// About 64 significant digits.
+    QuadDouble,
+}
+
+impl Precision {
+    pub const ALL: [Precision; 5] = [Precision::Auto, Precision::F32, Precision::F64, Precision::DoubleDouble, Precision::QuadDouble];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Precision::Auto => "auto",
+            Precision::F32 => "f32",
+            Precision::F64 => "f64",
+            Precision::DoubleDouble => "double-double",
+            Precision::QuadDouble => "quad-double",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Precision> {
+        Precision::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// The smallest pixel size, relative to the size of the coordinates, each
+/// precision is used for by Precision::Auto. Iterating loses a few digits, so
+/// these leave a margin over the precision of a single value.
+const F64_MIN_RELATIVE_PIXEL: f64 = 1e-13;
+const DOUBLE_DOUBLE_MIN_RELATIVE_PIXEL: f64 = 1e-28;
+const QUAD_DOUBLE_MIN_RELATIVE_PIXEL: f64 = 1e-58;
+
+/// The smallest scale of a view. Pixel sizes and offsets from the center are
+/// f64s, which underflow not far below this.
+pub const MIN_SCALE: f64 = 1e-290;
+
+/// The width of the complex plane shown when looking at a whole Julia set.
+pub const JULIA_SCALE: f64 = 3.5;
+
+#[derive(Clone, PartialEq)]
 pub struct State {
     pub width: u32,
     pub height: u32,
     pub max_iterations: u32,
-    pub scale: f64,
-    pub center: Complex64,
+    /// The width of the view in the complex plane.
+    pub scale: BigDecimal,
+    pub center: BigComplex,
+    /// Counterclockwise rotation of the view around its center, in radians.
+    pub rotation: f64,
     pub fractal_type: FractalType,
+    pub sampling: Sampling,
+    pub precision: Precision,
 }
 
 impl State {
@@ -35,81 +103,278 @@ impl State {
             width: w,
             height: h,
             max_iterations: 500,
-            scale: 2.0,
-            center: mandelbrot::Complex64::new(0., 0.),
+            scale: BigDecimal::from(2),
+            center: BigComplex::default(),
+            rotation: 0.0,
             fractal_type: mandelbrot::FractalType::Mandelbrot,
+            sampling: Sampling::Rectangular,
+            precision: Precision::Auto,
+        }
+    }
+
+    /// The starting view of this state's fractal type, keeping the render
+    /// size.
+    pub fn home(&self) -> Self {
+        let mut home = State::new(self.width, self.height);
+        home.fractal_type = self.fractal_type.clone();
+        match home.fractal_type {
+            FractalType::Mandelbrot => home.center.re -= bigcoord::from_f64(0.5),
+            FractalType::Julia(_) => home.scale = bigcoord::from_f64(JULIA_SCALE),
         }
+        home
     }
 
     pub fn aspect(&self) -> f64 {
         self.width as f64 / self.height as f64
     }
 
+    /// The scale converted to f64, for the sizes of pixels.
+    pub fn scale_f64(&self) -> f64 {
+        bigcoord::to_f64(&self.scale)
+    }
+
+    /// The center rounded to f64, which is only accurate enough to work
+    /// with offsets from it in shallow views.
+    pub fn center_f64(&self) -> Complex64 {
+        self.center.to_complex()
+    }
+
     pub fn increments(&self) -> (f64, f64) {
+        let scale = self.scale_f64();
         (
-            self.scale / self.width as f64,
-            (self.scale / self.aspect()) / self.height as f64,
+            scale / self.width as f64,
+            (scale / self.aspect()) / self.height as f64,
         )
     }
 
+    /// The rotation of the view as a unit complex number, offsets from the
+    /// center are multiplied by it.
+    pub fn rotor(&self) -> Complex64 {
+        Complex64::from_polar(1.0, self.rotation)
+    }
+
     pub fn pixel_to_mandelbrot_coord(&self, x: i32, y: i32) -> Complex64 {
+        self.center_f64() + self.pixel_offset(x, y)
+    }
+
+    /// The offset of pixel (x, y) from the center, which stays accurate at
+    /// any depth.
+    pub fn pixel_offset(&self, x: i32, y: i32) -> Complex64 {
         let (x_incr, y_incr) = self.increments();
         let x = x - (self.width as i32/2);
         let y = (self.height as i32/2) - y;
-        Complex64::new(self.center.re + ((x as f64) * x_incr), self.center.im +((y as f64) * y_incr))
+        Complex64::new((x as f64) * x_incr, (y as f64) * y_incr) * self.rotor()
+    }
 
+    /// The number of decimal places points of this view are kept to.
+    fn point_places(&self) -> i64 {
+        let (x_incr, _) = self.increments();
+        bigcoord::places(x_incr)
+    }
+
+    /// pixel_to_mandelbrot_coord with arbitrary precision.
+    pub fn pixel_point(&self, x: i32, y: i32) -> BigComplex {
+        self.center.offset(self.pixel_offset(x, y), self.point_places())
+    }
+
+    /// Move the center by offset, keeping as many decimal places as the size
+    /// of a pixel needs.
+    pub fn move_center(&mut self, offset: Complex64) {
+        self.center = self.center.offset(offset, self.point_places());
+    }
+
+    /// The view of rows first..first + rows of this one, whose pixels are
+    /// exactly the pixels of those rows.
+    pub fn band(&self, first: u32, rows: u32) -> State {
+        let mut band = self.clone();
+        band.height = rows;
+        match self.sampling {
+            Sampling::Rectangular => {
+                let (_, y_incr) = self.increments();
+                let dy = (self.height / 2) as f64 - (first + rows / 2) as f64;
+                band.move_center(Complex64::new(0.0, dy * y_incr) * self.rotor());
+            }
+            Sampling::LogPolar => band.scale = bigcoord::from_f64(self.log_polar_radius(first as f64) * 2.0),
+        }
+        band
+    }
+
+    /// Multiply the scale by factor, stopping at MIN_SCALE.
+    pub fn zoom(&mut self, factor: f64) {
+        self.scale = bigcoord::scale_by(&self.scale, factor);
+        if bigcoord::log10(&self.scale) < MIN_SCALE.log10() {
+            self.scale = bigcoord::from_f64(MIN_SCALE);
+        }
+    }
+
+    /// The precision this state is computed with, resolving Auto.
+    pub fn render_precision(&self) -> Precision {
+        if self.precision != Precision::Auto {
+            return self.precision;
+        }
+        let relative = self.relative_pixel();
+        if relative >= F64_MIN_RELATIVE_PIXEL {
+            Precision::F64
+        } else if relative >= DOUBLE_DOUBLE_MIN_RELATIVE_PIXEL {
+            Precision::DoubleDouble
+        } else {
+            Precision::QuadDouble
+        }
+    }
+
+    /// Whether pixels are smaller than render_precision can tell apart, so
+    /// the image is noise.
+    pub fn precision_exhausted(&self) -> bool {
+        let min_relative = match self.render_precision() {
+            Precision::F64 => F64_MIN_RELATIVE_PIXEL,
+            Precision::DoubleDouble => DOUBLE_DOUBLE_MIN_RELATIVE_PIXEL,
+            _ => QUAD_DOUBLE_MIN_RELATIVE_PIXEL,
+        };
+        self.relative_pixel() < min_relative
+    }
+
+    /// The size of a pixel relative to the coordinates around it.
+    fn relative_pixel(&self) -> f64 {
+        let (x_incr, _) = self.increments();
+        x_incr / self.center_f64().norm().max(1.0)
     }
-}
 
-pub type DataRow = Vec<FractalSample>;
+    /// The radius of row y of a log polar render.
+    pub fn log_polar_radius(&self, y: f64) -> f64 {
+        self.scale_f64() / 2.0 * (-std::f64::consts::TAU * y / self.width as f64).exp()
+    }
+
+    /// The offset from the center of the point at (x, y) in pixels, which
+    /// need not be whole, such as the samples within a pixel.
+    pub fn sample_offset(&self, x: f64, y: f64) -> Complex64 {
+        match self.sampling {
+            Sampling::Rectangular => {
+                let (x_incr, y_incr) = self.increments();
+                let x = x - (self.width / 2) as f64;
+                let y = (self.height / 2) as f64 - y;
+                Complex64::new(x * x_incr, y * y_incr) * self.rotor()
+            }
+            Sampling::LogPolar => self.log_polar_offset(x, y),
+        }
+    }
+
+    /// The offset from the center of the point sampled for pixel (x, y) of
+    /// a log polar render.
+    pub fn log_polar_offset(&self, x: f64, y: f64) -> Complex64 {
+        let angle = self.rotation + std::f64::consts::TAU * x / self.width as f64;
+        Complex64::from_polar(self.log_polar_radius(y), angle)
+    }
+
+    /// The inverse of pixel_to_mandelbrot_coord, returning fractional pixel
+    /// coordinates which may lie outside of the image.
+    pub fn mandelbrot_coord_to_pixel(&self, c: Complex64) -> (f64, f64) {
+        self.offset_to_pixel(c - self.center_f64())
+    }
+
+    /// The inverse of pixel_offset.
+    pub fn offset_to_pixel(&self, offset: Complex64) -> (f64, f64) {
+        let (x_incr, y_incr) = self.increments();
+        let offset = offset * self.rotor().conj();
+        let x = offset.re / x_incr + (self.width as i32/2) as f64;
+        let y = (self.height as i32/2) as f64 - offset.im / y_incr;
+        (x, y)
+    }
+
+    /// Scale the view by factor while keeping the point under pixel (x, y)
+    /// fixed on screen.
+    pub fn zoom_at(&mut self, x: i32, y: i32, factor: f64) {
+        let anchor = self.pixel_offset(x, y);
+        self.move_center(anchor * (1.0 - factor));
+        self.zoom(factor);
+    }
+
+    /// Zoom so the pixel rectangle between (x0, y0) and (x1, y1) fills the
+    /// view. The rectangle is grown along one axis to match the aspect ratio.
+    pub fn zoom_to_rect(&mut self, x0: i32, y0: i32, x1: i32, y1: i32) {
+        let (x_incr, y_incr) = self.increments();
+        let a = self.pixel_offset(x0, y0);
+        let b = self.pixel_offset(x1, y1);
+        let width = (x1 - x0).abs() as f64 * x_incr;
+        let height = (y1 - y0).abs() as f64 * y_incr;
+        self.move_center((a + b) / 2.0);
+        self.zoom(width.max(height * self.aspect()) / self.scale_f64());
+    }
+
+    /// The orbit of a point in the complex plane for this state's fractal.
+    pub fn orbit_at(&self, point: Complex64) -> Orbit {
+        match self.fractal_type {
+            FractalType::Mandelbrot => orbit(point, point, self.max_iterations),
+            FractalType::Julia(c) => orbit(c, point, self.max_iterations),
+        }
+    }
+
+    /// Move the view so the image content shifts by (dx, dy) pixels.
+    pub fn pan_pixels(&mut self, dx: f64, dy: f64) {
+        let (x_incr, y_incr) = self.increments();
+        self.move_center(-Complex64::new(dx * x_incr, -dy * y_incr) * self.rotor());
+    }
+}
 
-pub struct Data {
+/// The samples of a render, row after row in a single buffer.
+pub struct Data<S = FractalSample> {
     pub state: State,
-    pub fractal_data: Vec<Vec<FractalSample>>,
+    samples: Vec<S>,
 }
 
-impl Data {
+impl<S: Sample> Data<S> {
     pub fn new(state: State) -> Self {
         if state.width == 0 || state.height == 0 {
             panic!("Bad dimensions in fractal state");
         }
-        let mut data: Vec<Vec<FractalSample>> = Vec::new();
-        data.reserve(state.height as usize);
-        for _y in 0..state.height {
-            let mut row: Vec<FractalSample> = Vec::new();
-            row.resize(
-                state.width as usize,
-                FractalSample {
-                    z: Complex64::new(0., 0.),
-                    escape: 0,
-                },
-            );
-            data.push(row);
-        }
+        let len = state.width as usize * state.height as usize;
         Self {
             state,
-            fractal_data: data,
+            samples: vec![S::default(); len],
         }
     }
 
-    pub fn resize(&mut self) {
-        if self.state.width == 0 || self.state.height == 0 {
-            panic!("Bad dimensions in fractal state");
-        }
-        let mut data: Vec<Vec<FractalSample>> = Vec::new();
-        data.reserve(self.state.height as usize);
-        for _y in 0..self.state.height {
-            let mut row: Vec<FractalSample> = Vec::new();
-            row.resize(
-                self.state.width as usize,
-                FractalSample {
-                    z: Complex64::new(0., 0.),
-                    escape: 0,
-                },
-            );
-            data.push(row);
-        }
-        self.fractal_data = data;
+    /// The samples of row y.
+    pub fn row(&self, y: usize) -> &[S] {
+        let width = self.state.width as usize;
+        &self.samples[y * width..(y + 1) * width]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [S] {
+        let width = self.state.width as usize;
+        &mut self.samples[y * width..(y + 1) * width]
+    }
+
+    /// Every row from the top.
+    pub fn rows(&self) -> std::slice::ChunksExact<'_, S> {
+        self.samples.chunks_exact(self.state.width as usize)
+    }
+
+    pub fn sample(&self, x: usize, y: usize) -> S {
+        self.samples[y * self.state.width as usize + x]
+    }
+
+    /// Every sample, row after row.
+    pub fn samples(&self) -> &[S] {
+        &self.samples
+    }
+
+    pub fn samples_mut(&mut self) -> &mut [S] {
+        &mut self.samples
+    }
+}
+
+/// What a render keeps of each point. The kernels produce FractalSamples,
+/// which are converted as they are stored.
+pub trait Sample: Copy + Default + Send + Sync {
+    fn from_sample(sample: FractalSample) -> Self;
+
+    fn escape(&self) -> u32;
+
+    /// The continuous escape count, which varies smoothly between the bands
+    /// of the integer escape count. Samples without z only have the integer
+    /// count.
+    fn smooth(&self) -> f64 {
+        self.escape() as f64
     }
 }
 
@@ -119,6 +384,41 @@ pub struct FractalSample {
     pub escape: u32,
 }
 
+impl Sample for FractalSample {
+    fn from_sample(sample: FractalSample) -> Self {
+        sample
+    }
+
+    fn escape(&self) -> u32 {
+        self.escape
+    }
+
+    fn smooth(&self) -> f64 {
+        let magnitude = self.z.norm();
+        if magnitude <= 1.0 {
+            return self.escape as f64;
+        }
+        self.escape as f64 + 1.0 - magnitude.ln().ln() / std::f64::consts::LN_2
+    }
+}
+
+/// Only the escape count of a point, a sixth of the size of a
+/// FractalSample, for large renders that are only colored.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct EscapeSample {
+    pub escape: u32,
+}
+
+impl Sample for EscapeSample {
+    fn from_sample(sample: FractalSample) -> Self {
+        Self { escape: sample.escape }
+    }
+
+    fn escape(&self) -> u32 {
+        self.escape
+    }
+}
+
 fn mandelbrot_f(
     c: Complex64,
     z0: Complex64,
@@ -128,83 +428,338 @@ fn mandelbrot_f(
     let mut z = z0;
 
     let mut i = cur_iterations;
-    while i < max_iterations && z.abs() < 2.0 {
+    while i < max_iterations && z.norm_sqr() < 4.0 {
         z = z * z + c;
         i += 1;
     }
     FractalSample { z, escape: i }
 }
 
+/// The longest cycle looked for when detecting the period of an orbit.
+const MAX_PERIOD: usize = 64;
+const PERIOD_TOLERANCE: f64 = 1e-9;
+
+/// Every value of z visited while iterating a single point.
+pub struct Orbit {
+    /// The values of z, starting with z0.
+    pub points: Vec<Complex64>,
+    pub escape: u32,
+    /// The period of the cycle the orbit settled into, for points that did
+    /// not escape.
+    pub period: Option<usize>,
+}
+
+impl Orbit {
+    pub fn final_z(&self) -> Complex64 {
+        *self.points.last().unwrap()
+    }
+
+    pub fn escaped(&self, max_iterations: u32) -> bool {
+        self.escape < max_iterations
+    }
+}
+
+/// Iterate a single point the same way mandelbrot_f does, keeping the orbit.
+pub fn orbit(c: Complex64, z0: Complex64, max_iterations: u32) -> Orbit {
+    let mut points = vec![z0];
+    let mut z = z0;
+    let mut i = 0;
+    while i < max_iterations && z.norm_sqr() < 4.0 {
+        z = z * z + c;
+        points.push(z);
+        i += 1;
+    }
+    let period = if i < max_iterations { None } else { detect_period(&points) };
+    Orbit {
+        points,
+        escape: i,
+        period,
+    }
+}
+
+/// Find the smallest p where the last point of the orbit comes back to
+/// itself after p iterations.
+fn detect_period(points: &[Complex64]) -> Option<usize> {
+    let last = *points.last()?;
+    let tolerance = PERIOD_TOLERANCE * last.abs().max(1.0);
+    (1..=MAX_PERIOD.min(points.len() - 1)).find(|p| (points[points.len() - 1 - p] - last).abs() < tolerance)
+}
+
+/// The version of the iteration code. Bump it whenever a change alters the
+/// results of any view, so results cached on disk are thrown away.
+pub const KERNEL_VERSION: u32 = 1;
+
+/// The implementation of the iteration loop.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Kernel {
+    /// One point at a time, supported everywhere.
+    Scalar,
+    /// Several points at a time with AVX instructions.
+    Vector,
+}
+
+impl Kernel {
+    /// The fastest kernel the CPU supports.
+    pub fn detect() -> Kernel {
+        if simd::available() {
+            Kernel::Vector
+        } else {
+            Kernel::Scalar
+        }
+    }
+}
+
 fn mandelbrot_row(
-    mut x_cur: f64,
-    y_cur: f64,
-    x_incr: f64,
+    coord: impl Fn(u32) -> Complex64,
     state: &State,
-    data_row: &mut Vec<FractalSample>,
+    data_row: &mut [FractalSample],
+    kernel: Kernel,
 ) {
-    for x in 0..state.width {
-        let z = Complex64::new(x_cur, y_cur);
+    if kernel == Kernel::Vector {
+        simd::iterate_row(|x| (coord(x), coord(x)), state.max_iterations, data_row);
+        return;
+    }
+    for x in 0..data_row.len() as u32 {
+        let z = coord(x);
         let c = z;
 
         data_row[x as usize] = mandelbrot_f(c, z, 0, state.max_iterations);
-        x_cur += x_incr;
     }
 }
 
 fn julia_row(
-    mut x_cur: f64,
-    y_cur: f64,
-    x_incr: f64,
+    coord: impl Fn(u32) -> Complex64,
     c: Complex64,
     state: &State,
-    data_row: &mut Vec<FractalSample>,
+    data_row: &mut [FractalSample],
+    kernel: Kernel,
 ) {
-    for x in 0..state.width {
-        let z = Complex64::new(x_cur, y_cur);
+    if kernel == Kernel::Vector {
+        simd::iterate_row(|x| (c, coord(x)), state.max_iterations, data_row);
+        return;
+    }
+    for x in 0..data_row.len() as u32 {
+        let z = coord(x);
 
         data_row[x as usize] = mandelbrot_f(c, z, 0, state.max_iterations);
-        x_cur += x_incr;
-    }
-}
-
-pub fn compute_mandelbrot(fd: &mut Data) {
-    if fd.fractal_data.len() != fd.state.height as usize {
-        panic!("sample array is the wrong size");
-    }
-    let aspect = fd.state.aspect();
-    let (x_incr, y_incr) = fd.state.increments();
-    let mut y_cur = fd.state.center.im + (fd.state.scale / aspect) / 2.0;
-    let x_cur = fd.state.center.re - fd.state.scale / 2.0;
-
-    match fd.state.fractal_type {
-        FractalType::Mandelbrot => {
-            fd.fractal_data
-                .par_iter_mut()
-                .enumerate()
-                .for_each(|entry| {
-                    mandelbrot_row(
-                        x_cur,
-                        y_cur - ((entry.0 as f64) * y_incr),
-                        x_incr,
-                        &fd.state,
-                        entry.1,
-                    );
-                });
-        }
-        FractalType::Julia(c) => {
-            fd.fractal_data
-                .par_iter_mut()
-                .enumerate()
-                .for_each(|entry| {
-                    julia_row(
-                        x_cur,
-                        y_cur - ((entry.0 as f64) * y_incr),
-                        x_incr,
-                        c,
-                        &fd.state,
-                        entry.1,
-                    );
-                });
+    }
+}
+
+/// The offset from the center of the point sampled by each pixel of row y.
+/// The center is added in the render precision, so deep views keep the
+/// detail adding it in f64 would lose.
+fn row_offsets(state: &State, y: u32) -> impl Fn(u32) -> Complex64 + '_ {
+    let (x_incr, y_incr) = state.increments();
+    let rotor = state.rotor();
+    let dy = (state.height as i32 / 2 - y as i32) as f64 * y_incr;
+    move |x| match state.sampling {
+        Sampling::Rectangular => Complex64::new((x as i32 - state.width as i32 / 2) as f64 * x_incr, dy) * rotor,
+        Sampling::LogPolar => state.log_polar_offset(x as f64, y as f64),
+    }
+}
+
+/// The center of a view as expansions, converted from its arbitrary
+/// precision form once per render.
+type CenterParts = ([f64; 4], [f64; 4]);
+
+/// The constant and starting value of z for each point of a row, given its
+/// offset from the center, in the arithmetic of T.
+fn row_points<'a, T: Real + 'a>(
+    state: &'a State,
+    center: &CenterParts,
+    offset: impl Fn(u32) -> Complex64 + 'a,
+) -> impl Fn(u32) -> ((T, T), (T, T)) + 'a {
+    let center = (T::from_expansion(center.0), T::from_expansion(center.1));
+    let julia = match state.fractal_type {
+        FractalType::Mandelbrot => None,
+        FractalType::Julia(c) => Some((T::from_f64(c.re), T::from_f64(c.im))),
+    };
+    move |x| {
+        let o = offset(x);
+        let z = (center.0 + T::from_f64(o.re), center.1 + T::from_f64(o.im));
+        (julia.unwrap_or(z), z)
+    }
+}
+
+/// The same iteration as mandelbrot_f for any precision.
+fn iterate<T: Real>(c: (T, T), z0: (T, T), max_iterations: u32) -> FractalSample {
+    let (mut re, mut im) = z0;
+    let mut i = 0;
+    while i < max_iterations && (re * re + im * im).to_f64() < 4.0 {
+        (re, im) = (re * re - im * im + c.0, re * im + im * re + c.1);
+        i += 1;
+    }
+    FractalSample {
+        z: Complex64::new(re.to_f64(), im.to_f64()),
+        escape: i,
+    }
+}
+
+fn generic_row<T: Real>(state: &State, center: &CenterParts, offset: impl Fn(u32) -> Complex64, data_row: &mut [FractalSample]) {
+    let points = row_points::<T>(state, center, offset);
+    for (x, sample) in data_row.iter_mut().enumerate() {
+        let (c, z) = points(x as u32);
+        *sample = iterate(c, z, state.max_iterations);
+    }
+}
+
+/// Compute the points at offset(i) from the center into data_row[i].
+fn compute_row(
+    state: &State,
+    center: &CenterParts,
+    offset: impl Fn(u32) -> Complex64,
+    data_row: &mut [FractalSample],
+    kernel: Kernel,
+) {
+    match (state.render_precision(), kernel) {
+        (Precision::F32, Kernel::Vector) => simd::iterate_row_f32(row_points::<f32>(state, center, offset), state.max_iterations, data_row),
+        (Precision::F32, Kernel::Scalar) => generic_row::<f32>(state, center, offset, data_row),
+        (Precision::DoubleDouble, _) => generic_row::<DoubleDouble>(state, center, offset, data_row),
+        (Precision::QuadDouble, _) => generic_row::<QuadDouble>(state, center, offset, data_row),
+        (Precision::F64 | Precision::Auto, _) => {
+            let center = Complex64::new(center.0[0], center.1[0]);
+            let coord = |x| center + offset(x);
+            match state.fractal_type {
+                FractalType::Mandelbrot => mandelbrot_row(coord, state, data_row, kernel),
+                FractalType::Julia(c) => julia_row(coord, c, state, data_row, kernel),
+            }
         }
     }
 }
+
+pub fn compute_mandelbrot<S: Sample>(fd: &mut Data<S>) {
+    compute_mandelbrot_with(fd, Kernel::detect());
+}
+
+/// Compute the fractal with a particular kernel, which must be supported by
+/// the CPU.
+pub fn compute_mandelbrot_with<S: Sample>(fd: &mut Data<S>, kernel: Kernel) {
+    compute_rows(fd, kernel, &Progress::new());
+}
+
+/// compute_mandelbrot, advancing progress by one as each row is finished so
+/// another thread can follow it. Setting the total is up to the caller, so a
+/// job made of several renders can count them all.
+pub fn compute_mandelbrot_with_progress<S: Sample>(fd: &mut Data<S>, progress: &Progress) {
+    compute_rows(fd, Kernel::detect(), progress);
+}
+
+fn compute_rows<S: Sample>(fd: &mut Data<S>, kernel: Kernel, progress: &Progress) {
+    let state = &fd.state;
+    let width = state.width as usize;
+    let center = state.center.expansion();
+    // each thread computes into a row of full samples, which are then
+    // converted to the stored kind
+    fd.samples
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each_init(
+            || vec![FractalSample::default(); width],
+            |computed, (y, row)| {
+                compute_row(state, &center, row_offsets(state, y as u32), computed, kernel);
+                for (sample, computed) in row.iter_mut().zip(computed.iter()) {
+                    *sample = S::from_sample(*computed);
+                }
+                progress.advance(1);
+            },
+        );
+}
+
+/// The samples at the given offsets from the center of state, such as the
+/// extra samples of supersampling, computed like the pixels of state.
+pub fn compute_points(state: &State, offsets: &[Complex64]) -> Vec<FractalSample> {
+    let mut samples = vec![FractalSample::default(); offsets.len()];
+    if !samples.is_empty() {
+        let center = state.center.expansion();
+        compute_row(state, &center, |i| offsets[i as usize], &mut samples, Kernel::detect());
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Views to move around in: a plain one, a rotated one and one deeper
+    /// than f64 can tell pixels apart in.
+    fn views() -> Vec<State> {
+        let mut rotated = State::new(640, 480).home();
+        rotated.rotation = 0.7;
+        let mut deep = State::new(640, 480);
+        deep.center = BigComplex::parse("-1.7499576837060935036022145, 0.0000000000001").unwrap();
+        deep.scale = "1e-25".parse().unwrap();
+        vec![State::new(640, 480).home(), rotated, deep]
+    }
+
+    /// How far apart pixel a of one view and pixel b of another are, in
+    /// pixels of the second.
+    fn pixels_apart(view_a: &State, a: (i32, i32), view_b: &State, b: (i32, i32)) -> f64 {
+        let (x_incr, _) = view_b.increments();
+        view_a.pixel_point(a.0, a.1).difference(&view_b.pixel_point(b.0, b.1)).norm() / x_incr
+    }
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_cursor() {
+        for view in views() {
+            for factor in [0.5, 0.8, 1.25, 2.0] {
+                let mut zoomed = view.clone();
+                zoomed.zoom_at(100, 400, factor);
+                assert!(pixels_apart(&view, (100, 400), &zoomed, (100, 400)) < 1e-3);
+                assert!(pixels_apart(&view, (320, 240), &zoomed, (320, 240)) > 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn panning_moves_the_image_with_the_mouse() {
+        for view in views() {
+            let mut panned = view.clone();
+            panned.pan_pixels(30.0, -12.0);
+            assert!(pixels_apart(&view, (200, 200), &panned, (230, 188)) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn zooming_to_a_rectangle_fills_the_view_with_it() {
+        for view in views() {
+            let mut zoomed = view.clone();
+            zoomed.zoom_to_rect(260, 220, 100, 100);
+            assert!(pixels_apart(&view, (100, 100), &zoomed, (0, 0)) < 1e-3);
+            assert!(pixels_apart(&view, (260, 220), &zoomed, (640, 480)) < 1e-3);
+
+            // a tall rectangle is widened around its center to the view's shape
+            let mut zoomed = view.clone();
+            zoomed.zoom_to_rect(300, 100, 340, 400);
+            assert!(pixels_apart(&view, (320, 250), &zoomed, (320, 240)) < 1e-3);
+            assert!(pixels_apart(&view, (300, 100), &zoomed, (320 - 32, 0)) < 1e-3);
+            assert!(pixels_apart(&view, (340, 400), &zoomed, (320 + 32, 480)) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn zooming_stops_at_the_smallest_scale() {
+        let mut view = views().pop().unwrap();
+        view.zoom(1e-200);
+        view.zoom(1e-200);
+        assert_eq!(view.scale, bigcoord::from_f64(MIN_SCALE));
+        let (x_incr, _) = view.increments();
+        assert!(x_incr > 0.0);
+        assert!((pixels_apart(&view, (10, 10), &view, (11, 10)) - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn precision_runs_out_past_quad_double() {
+        let mut view = views().pop().unwrap();
+        view.scale = "1e-20".parse().unwrap();
+        assert_eq!(view.render_precision(), Precision::DoubleDouble);
+        assert!(!view.precision_exhausted());
+        view.precision = Precision::F64;
+        assert!(view.precision_exhausted());
+        view.precision = Precision::Auto;
+        view.scale = "1e-50".parse().unwrap();
+        assert_eq!(view.render_precision(), Precision::QuadDouble);
+        assert!(!view.precision_exhausted());
+        view.scale = "1e-60".parse().unwrap();
+        assert!(view.precision_exhausted());
+    }
+}
@@ -0,0 +1,335 @@
+use eframe::egui;
+use egui::{Color32, ColorImage};
+use crate::mandelbrot::{Complex64, State};
+
+/// Roughly how far apart, in pixels, grid lines should be.
+const GRID_TARGET_SPACING: f64 = 120.0;
+/// More grid lines than this are not drawn, which only happens when the
+/// view has lost the precision to place them.
+const MAX_GRID_LINES: i64 = 1000;
+/// Roughly what fraction of the image width the scale bar should cover.
+const SCALE_BAR_FRACTION: f64 = 0.2;
+const MARGIN: f32 = 20.0;
+const TICK: f32 = 6.0;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct OverlayOptions {
+    pub axes: bool,
+    pub grid: bool,
+    pub scale_bar: bool,
+}
+
+impl OverlayOptions {
+    pub fn any(&self) -> bool {
+        self.axes || self.grid || self.scale_bar
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Style {
+    Grid,
+    Axis,
+    ScaleBar,
+}
+
+impl Style {
+    fn color(self) -> ([u8; 3], f32) {
+        match self {
+            Style::Grid => ([255, 255, 255], 0.3),
+            Style::Axis => ([255, 255, 255], 0.8),
+            Style::ScaleBar => ([255, 255, 255], 1.0),
+        }
+    }
+
+    fn width(self) -> f32 {
+        match self {
+            Style::Grid => 1.0,
+            Style::Axis => 1.0,
+            Style::ScaleBar => 3.0,
+        }
+    }
+
+    fn color32(self) -> Color32 {
+        let ([r, g, b], alpha) = self.color();
+        Color32::from_rgba_unmultiplied(r, g, b, (alpha * 255.0) as u8)
+    }
+}
+
+/// Something to draw over the image, in pixel coordinates of the state it
+/// was laid out for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mark {
+    Line { from: (f32, f32), to: (f32, f32), style: Style },
+    /// Text whose top left corner is at (x, y).
+    Label { x: f32, y: f32, text: String },
+}
+
+/// The 1, 2 or 5 times a power of ten closest to value.
+fn nice_step(value: f64) -> f64 {
+    let magnitude = 10f64.powf(value.log10().floor());
+    let mantissa = value / magnitude;
+    let nice = if mantissa < 1.5 {
+        1.0
+    } else if mantissa < 3.5 {
+        2.0
+    } else if mantissa < 7.5 {
+        5.0
+    } else {
+        10.0
+    };
+    nice * magnitude
+}
+
+/// Format a coordinate with just enough decimals to distinguish multiples
+/// of step.
+fn format_value(value: f64, step: f64) -> String {
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    // avoid printing "-0"
+    let value = if value.abs() < step / 2.0 { 0.0 } else { value };
+    if value != 0.0 && !(1e-4..1e6).contains(&value.abs()) {
+        let digits = (value.abs().log10().floor() - step.log10().floor()).max(0.0) as usize;
+        format!("{:.*e}", digits, value)
+    } else {
+        format!("{:.*}", decimals, value)
+    }
+}
+
+/// Clip the segment from a to b to the rectangle from the origin to
+/// (width, height), or None if it lies outside.
+fn clip_segment(a: (f32, f32), b: (f32, f32), width: f32, height: f32) -> Option<((f32, f32), (f32, f32))> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let (mut t0, mut t1) = (0.0f32, 1.0f32);
+    for (p, q) in [(-dx, a.0), (dx, width - a.0), (-dy, a.1), (dy, height - a.1)] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return None;
+    }
+    Some(((a.0 + dx * t0, a.1 + dy * t0), (a.0 + dx * t1, a.1 + dy * t1)))
+}
+
+/// The multiples of step from min to max, for grid lines. None when step
+/// is too small to tell the lines apart in f64, as in deep zooms, where
+/// adding it would not change the coordinate.
+fn grid_values(min: f64, max: f64, step: f64) -> Option<Vec<f64>> {
+    let magnitude = min.abs().max(max.abs());
+    let usable = max > min && step > magnitude * f64::EPSILON;
+    if !usable {
+        return None;
+    }
+    let (first, last) = ((min / step).ceil() as i64, (max / step).floor() as i64);
+    if last.saturating_sub(first) >= MAX_GRID_LINES {
+        return None;
+    }
+    Some((first..=last).map(|k| k as f64 * step).collect())
+}
+
+/// Lay out the overlay for state.
+pub fn marks(state: &State, options: &OverlayOptions) -> Vec<Mark> {
+    let mut marks = Vec::new();
+    let width = state.width as f32;
+    let height = state.height as f32;
+    let (x_incr, y_incr) = state.increments();
+    // the range of coordinates covered by the view, which is larger than
+    // the view itself when it is rotated
+    let corners = [(0, 0), (state.width as i32, 0), (0, state.height as i32), (state.width as i32, state.height as i32)]
+        .map(|(x, y)| state.pixel_to_mandelbrot_coord(x, y));
+    let re_min = corners.iter().map(|c| c.re).fold(f64::INFINITY, f64::min);
+    let re_max = corners.iter().map(|c| c.re).fold(f64::NEG_INFINITY, f64::max);
+    let im_min = corners.iter().map(|c| c.im).fold(f64::INFINITY, f64::min);
+    let im_max = corners.iter().map(|c| c.im).fold(f64::NEG_INFINITY, f64::max);
+    let to_pixel = |re: f64, im: f64| {
+        let (x, y) = state.mandelbrot_coord_to_pixel(Complex64::new(re, im));
+        (x as f32, y as f32)
+    };
+    let clipped = |re0: f64, im0: f64, re1: f64, im1: f64| clip_segment(to_pixel(re0, im0), to_pixel(re1, im1), width, height);
+
+    if options.grid {
+        let step = nice_step(x_incr * GRID_TARGET_SPACING);
+        for re in grid_values(re_min, re_max, step).unwrap_or_default() {
+            if let Some((from, to)) = clipped(re, im_min, re, im_max) {
+                // label the end nearest the top
+                let (x, y) = if from.1 < to.1 { from } else { to };
+                marks.push(Mark::Line { from, to, style: Style::Grid });
+                marks.push(Mark::Label { x: x + 3.0, y: y + 3.0, text: format_value(re, step) });
+            }
+        }
+        let step = nice_step(y_incr * GRID_TARGET_SPACING);
+        for im in grid_values(im_min, im_max, step).unwrap_or_default() {
+            if let Some((from, to)) = clipped(re_min, im, re_max, im) {
+                // label the end nearest the left
+                let (x, y) = if from.0 < to.0 { from } else { to };
+                marks.push(Mark::Line { from, to, style: Style::Grid });
+                marks.push(Mark::Label { x: x + 3.0, y: y + 3.0, text: format!("{}i", format_value(im, step)) });
+            }
+        }
+    }
+
+    if options.axes {
+        if let Some((from, to)) = clipped(0.0, im_min, 0.0, im_max) {
+            marks.push(Mark::Line { from, to, style: Style::Axis });
+        }
+        if let Some((from, to)) = clipped(re_min, 0.0, re_max, 0.0) {
+            marks.push(Mark::Line { from, to, style: Style::Axis });
+        }
+    }
+
+    if options.scale_bar {
+        let length = nice_step(state.scale_f64() * SCALE_BAR_FRACTION);
+        let pixels = (length / x_incr) as f32;
+        let y = height - MARGIN;
+        let (x0, x1) = (MARGIN, MARGIN + pixels);
+        marks.push(Mark::Line { from: (x0, y), to: (x1, y), style: Style::ScaleBar });
+        marks.push(Mark::Line { from: (x0, y - TICK), to: (x0, y + TICK), style: Style::ScaleBar });
+        marks.push(Mark::Line { from: (x1, y - TICK), to: (x1, y + TICK), style: Style::ScaleBar });
+        marks.push(Mark::Label { x: x0, y: y - TICK - 16.0, text: format_value(length, length) });
+    }
+    marks
+}
+
+/// Draw the marks with egui, over the image shown in rect.
+pub fn paint(painter: &egui::Painter, marks: &[Mark], rect: egui::Rect, pixels_per_point: f32) {
+    let to_screen = |x: f32, y: f32| rect.min + egui::vec2(x, y) / pixels_per_point;
+    for mark in marks {
+        match mark {
+            Mark::Line { from, to, style } => {
+                painter.line_segment([to_screen(from.0, from.1), to_screen(to.0, to.1)], egui::Stroke::new(style.width(), style.color32()));
+            }
+            Mark::Label { x, y, text } => {
+                let pos = to_screen(*x, *y);
+                let font = egui::FontId::monospace(12.0);
+                painter.text(pos + egui::vec2(1.0, 1.0), egui::Align2::LEFT_TOP, text, font.clone(), Color32::BLACK);
+                painter.text(pos, egui::Align2::LEFT_TOP, text, font, Color32::WHITE);
+            }
+        }
+    }
+}
+
+/// Draw the marks into the pixels of an image, for exports.
+pub fn bake(image: &mut ColorImage, marks: &[Mark]) {
+    for mark in marks {
+        match mark {
+            Mark::Line { from, to, style } => draw_line(image, *from, *to, *style),
+            Mark::Label { x, y, text } => {
+                draw_text(image, *x + 1.0, *y + 1.0, text, [0, 0, 0]);
+                draw_text(image, *x, *y, text, [255, 255, 255]);
+            }
+        }
+    }
+}
+
+fn blend(image: &mut ColorImage, x: i64, y: i64, (rgb, alpha): ([u8; 3], f32)) {
+    let [w, h] = image.size;
+    if x < 0 || y < 0 || x >= w as i64 || y >= h as i64 {
+        return;
+    }
+    let pixel = &mut image.pixels[y as usize * w + x as usize];
+    let mix = |dst: u8, src: u8| (dst as f32 * (1.0 - alpha) + src as f32 * alpha).round() as u8;
+    *pixel = Color32::from_rgb(mix(pixel.r(), rgb[0]), mix(pixel.g(), rgb[1]), mix(pixel.b(), rgb[2]));
+}
+
+fn fill_rect(image: &mut ColorImage, x0: f32, y0: f32, x1: f32, y1: f32, color: ([u8; 3], f32)) {
+    // always cover at least one pixel so thin lines do not vanish
+    let (x0, x1) = (x0.min(x1).round() as i64, x0.max(x1).round() as i64);
+    let (y0, y1) = (y0.min(y1).round() as i64, y0.max(y1).round() as i64);
+    let (x1, y1) = (x1.max(x0 + 1), y1.max(y0 + 1));
+    let [w, h] = image.size;
+    for y in y0.max(0)..y1.min(h as i64) {
+        for x in x0.max(0)..x1.min(w as i64) {
+            blend(image, x, y, color);
+        }
+    }
+}
+
+fn draw_line(image: &mut ColorImage, from: (f32, f32), to: (f32, f32), style: Style) {
+    let half = style.width() / 2.0;
+    if from.0 == to.0 || from.1 == to.1 {
+        fill_rect(image, from.0.min(to.0) - half, from.1.min(to.1) - half, from.0.max(to.0) + half, from.1.max(to.1) + half, style.color());
+        return;
+    }
+    // step a pixel at a time along the major axis so diagonal lines have
+    // no gaps and are not blended twice
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let steps = dx.abs().max(dy.abs()).ceil().max(1.0) as i64;
+    let mut last = None;
+    for i in 0..=steps {
+        let t = i as f32 / steps as f32;
+        let (x, y) = ((from.0 + dx * t).round(), (from.1 + dy * t).round());
+        if last != Some((x, y)) {
+            fill_rect(image, x - half, y - half, x + half, y + half, style.color());
+            last = Some((x, y));
+        }
+    }
+}
+
+/// Pixel size of a font cell when baking labels.
+const FONT_SCALE: i64 = 2;
+const GLYPH_WIDTH: i64 = 3;
+const GLYPH_HEIGHT: usize = 5;
+
+/// A 3x5 pixel font covering the characters used in labels, one row per
+/// entry with the leftmost pixel in the highest bit.
+fn glyph(c: char) -> Option<[u8; GLYPH_HEIGHT]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        'e' => [0b000, 0b111, 0b111, 0b100, 0b111],
+        'i' => [0b010, 0b000, 0b010, 0b010, 0b010],
+        ' ' => [0; GLYPH_HEIGHT],
+        _ => return None,
+    })
+}
+
+fn draw_text(image: &mut ColorImage, x: f32, y: f32, text: &str, rgb: [u8; 3]) {
+    let (mut x, y) = (x.round() as i64, y.round() as i64);
+    for c in text.chars() {
+        if let Some(rows) = glyph(c) {
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                        let px = x + col * FONT_SCALE;
+                        let py = y + row as i64 * FONT_SCALE;
+                        fill_rect(image, px as f32, py as f32, (px + FONT_SCALE) as f32, (py + FONT_SCALE) as f32, (rgb, 1.0));
+                    }
+                }
+            }
+        }
+        x += (GLYPH_WIDTH + 1) * FONT_SCALE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_values_are_multiples_of_step() {
+        assert_eq!(grid_values(-0.25, 0.5, 0.25), Some(vec![-0.25, 0.0, 0.25, 0.5]));
+    }
+
+    #[test]
+    fn grid_values_give_up_below_f64_precision() {
+        let re = -0.7436438870371587;
+        assert_eq!(grid_values(re, re + 1e-16, 1e-18), None);
+        assert_eq!(grid_values(re, re, 1e-3), None);
+    }
+}
@@ -19,8 +19,8 @@ pub enum PaletteType {
 }
 
 impl PaletteType {
-    pub fn to_palette(&self) -> Palette {
-        match *self {
+    pub fn to_palette(self) -> Palette {
+        match self {
             PaletteType::BW => new_bw(),
             PaletteType::Color1Mod => new_color1_mod(),
             PaletteType::Color2Mod => new_color2_mod(),
@@ -28,6 +28,26 @@ impl PaletteType {
             PaletteType::Color2Lin => new_color2_lin(),
         }
     }
+
+    /// The palette rotated by offset entries.
+    pub fn to_palette_with_offset(self, offset: usize) -> Palette {
+        let mut p = self.to_palette();
+        p.offset = offset % p.palette.len();
+        p
+    }
+
+    /// Look up a palette by its variant name, ignoring case, e.g. "color1lin".
+    pub fn from_name(name: &str) -> Option<PaletteType> {
+        [
+            PaletteType::BW,
+            PaletteType::Color1Mod,
+            PaletteType::Color1Lin,
+            PaletteType::Color2Mod,
+            PaletteType::Color2Lin,
+        ]
+        .into_iter()
+        .find(|p| format!("{:?}", p).eq_ignore_ascii_case(name))
+    }
 }
 
 impl Display for PaletteType {
@@ -47,6 +67,9 @@ pub struct Palette {
     pub palette_type: PaletteType,
     pub color_mode: ColorMode,
     pub palette: PaletteData,
+    /// How many entries the colors are rotated by, so the palette can be
+    /// shifted without rebuilding it.
+    pub offset: usize,
 }
 
 pub fn new_bw() -> Palette {
@@ -58,6 +81,7 @@ pub fn new_bw() -> Palette {
         palette_type: PaletteType::BW,
         color_mode: ColorMode::LinearScale,
         palette: pd,
+        offset: 0,
     }
 }
 
@@ -71,6 +95,7 @@ pub fn new_color1_mod() -> Palette {
         palette_type: PaletteType::Color1Mod,
         color_mode: ColorMode::Modulus,
         palette: pd,
+        offset: 0,
     }
 }
 
@@ -91,6 +116,7 @@ pub fn new_color2_mod() -> Palette {
         palette_type: PaletteType::Color2Mod,
         color_mode: ColorMode::Modulus,
         palette: pd,
+        offset: 0,
     }
 }
 
@@ -104,6 +130,7 @@ pub fn new_color2_lin() -> Palette {
         palette_type: PaletteType::Color2Lin,
         color_mode: ColorMode::Modulus,
         palette: pd,
+        offset: 0,
     }
 }
 
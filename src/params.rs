@@ -0,0 +1,163 @@
+use std::fmt::Write;
+use bigdecimal::num_bigint::Sign;
+use bigdecimal::BigDecimal;
+use crate::bigcoord::{self, BigComplex};
+use crate::mandelbrot::{Complex64, FractalType, Precision, Sampling, State, MIN_SCALE};
+
+/// A `[name]` section of a parameter file and its `key = value` lines.
+///
+/// Lines before the first section header belong to a section with an empty
+/// name. Blank lines and lines starting with `#` are ignored.
+pub struct Section {
+    pub name: String,
+    pub values: Vec<(String, String)>,
+}
+
+pub fn parse(text: &str) -> Result<Vec<Section>, String> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        values: Vec::new(),
+    }];
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push(Section {
+                name: name.trim().to_string(),
+                values: Vec::new(),
+            });
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected `key = value`", line_no + 1))?;
+        let section = sections.last_mut().unwrap();
+        section.values.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    if sections[0].values.is_empty() {
+        sections.remove(0);
+    }
+    Ok(sections)
+}
+
+/// Parse a complex number written as `re, im`.
+pub fn parse_complex(value: &str) -> Option<Complex64> {
+    let (re, im) = value.split_once(',')?;
+    Some(Complex64::new(re.trim().parse().ok()?, im.trim().parse().ok()?))
+}
+
+/// Parse an image size written as `WxH`.
+pub fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (w, h) = value.split_once('x')?;
+    let (w, h) = (w.trim().parse().ok()?, h.trim().parse().ok()?);
+    if w == 0 || h == 0 {
+        return None;
+    }
+    Some((w, h))
+}
+
+/// A scale, which must be positive and no smaller than MIN_SCALE.
+pub fn parse_scale(value: &str) -> Option<BigDecimal> {
+    let scale: BigDecimal = value.trim().parse().ok()?;
+    (scale.sign() == Sign::Plus && bigcoord::log10(&scale) >= MIN_SCALE.log10()).then_some(scale)
+}
+
+/// Set the field of state named by key. Returns false for keys that do not
+/// describe a state, so callers can handle their own keys.
+pub fn apply_state_value(state: &mut State, key: &str, value: &str) -> Result<bool, String> {
+    let bad = || format!("bad value for {}: `{}`", key, value);
+    match key {
+        "size" => (state.width, state.height) = parse_size(value).ok_or_else(bad)?,
+        "center" => state.center = BigComplex::parse(value).ok_or_else(bad)?,
+        "scale" => state.scale = parse_scale(value).ok_or_else(bad)?,
+        "rotation" => state.rotation = value.parse::<f64>().map_err(|_| bad())?.to_radians(),
+        "iterations" => state.max_iterations = value.parse().map_err(|_| bad())?,
+        "julia" => state.fractal_type = FractalType::Julia(parse_complex(value).ok_or_else(bad)?),
+        "mandelbrot" => state.fractal_type = FractalType::Mandelbrot,
+        "precision" => state.precision = Precision::from_name(value).ok_or_else(bad)?,
+        "sampling" => {
+            state.sampling = match value {
+                "rectangular" => Sampling::Rectangular,
+                "log_polar" => Sampling::LogPolar,
+                _ => return Err(bad()),
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Write the lines describing state, in the form apply_state_value reads.
+pub fn write_state(out: &mut String, state: &State) {
+    writeln!(out, "size = {}x{}", state.width, state.height).unwrap();
+    writeln!(out, "center = {}", state.center).unwrap();
+    writeln!(out, "scale = {}", state.scale).unwrap();
+    writeln!(out, "rotation = {}", state.rotation.to_degrees()).unwrap();
+    writeln!(out, "iterations = {}", state.max_iterations).unwrap();
+    match state.fractal_type {
+        FractalType::Mandelbrot => writeln!(out, "mandelbrot = true").unwrap(),
+        FractalType::Julia(c) => writeln!(out, "julia = {}, {}", c.re, c.im).unwrap(),
+    }
+    if state.precision != Precision::Auto {
+        writeln!(out, "precision = {}", state.precision.name()).unwrap();
+    }
+    if state.sampling == Sampling::LogPolar {
+        writeln!(out, "sampling = log_polar").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_hold_their_values() {
+        let text = "title = zoom\n# a comment\n\n[keyframe]\nscale = 4\n[ keyframe ]\ncenter = -0.5, 0\n";
+        let sections = parse(text).unwrap();
+        let names: Vec<&str> = sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["", "keyframe", "keyframe"]);
+        assert_eq!(sections[0].values, [("title".to_string(), "zoom".to_string())]);
+        assert_eq!(sections[2].values, [("center".to_string(), "-0.5, 0".to_string())]);
+        assert_eq!(parse("[keyframe]\nscale 4").err().unwrap(), "line 2: expected `key = value`");
+    }
+
+    #[test]
+    fn values_are_checked() {
+        assert_eq!(parse_size("640x480"), Some((640, 480)));
+        assert_eq!(parse_size("0x480"), None);
+        assert_eq!(parse_complex("-0.75, 0.1"), Some(Complex64::new(-0.75, 0.1)));
+        assert_eq!(parse_complex("-0.75"), None);
+        assert_eq!(parse_scale("1e-40"), Some("1e-40".parse().unwrap()));
+        assert_eq!(parse_scale("0"), None);
+        assert_eq!(parse_scale("-2"), None);
+        assert_eq!(parse_scale("1e-290"), Some("1e-290".parse().unwrap()));
+        assert_eq!(parse_scale("1e-400"), None);
+        let mut state = State::new(64, 48);
+        assert!(apply_state_value(&mut state, "iterations", "many").is_err());
+        assert!(apply_state_value(&mut state, "sampling", "spiral").is_err());
+        assert_eq!(apply_state_value(&mut state, "frames", "10"), Ok(false));
+    }
+
+    #[test]
+    fn written_states_read_back() {
+        let mut state = State::new(320, 200);
+        state.center = BigComplex::parse("-1.7499576837060935036022145060706997072711, 0.0000000000001").unwrap();
+        state.scale = "3.5e-30".parse().unwrap();
+        state.rotation = 90f64.to_radians();
+        state.max_iterations = 4000;
+        state.fractal_type = FractalType::Julia(Complex64::new(-0.8, 0.156));
+        state.sampling = Sampling::LogPolar;
+        state.precision = Precision::QuadDouble;
+        let mut text = String::new();
+        write_state(&mut text, &state);
+        let mut read = State::new(1, 1);
+        for section in parse(&text).unwrap() {
+            for (key, value) in section.values {
+                assert_eq!(apply_state_value(&mut read, &key, &value), Ok(true), "{}", key);
+            }
+        }
+        assert!(read == state);
+    }
+}
@@ -0,0 +1,428 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// A floating point type the iteration kernel can be run with.
+pub trait Real: Copy + Send + Sync + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> {
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+
+    /// The value of an unevaluated sum of f64s, largest first, to the
+    /// precision of Self.
+    fn from_expansion(parts: [f64; 4]) -> Self {
+        Self::from_f64(parts[0])
+    }
+}
+
+impl Real for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Real for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+/// a + b and the rounding error of the sum.
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    let bb = s - a;
+    (s, (a - (s - bb)) + (b - bb))
+}
+
+/// two_sum for when |a| >= |b|.
+fn quick_two_sum(a: f64, b: f64) -> (f64, f64) {
+    let s = a + b;
+    (s, b - (s - a))
+}
+
+/// Split a into two halves of 26 bits, whose products with each other are
+/// exact.
+fn split(a: f64) -> (f64, f64) {
+    const SPLITTER: f64 = 134217729.0; // 2^27 + 1
+    let t = SPLITTER * a;
+    let hi = t - (t - a);
+    (hi, a - hi)
+}
+
+/// a * b and the rounding error of the product. This uses Dekker's method
+/// rather than mul_add, which is very slow on CPUs without FMA.
+fn two_prod(a: f64, b: f64) -> (f64, f64) {
+    let p = a * b;
+    let (a_hi, a_lo) = split(a);
+    let (b_hi, b_lo) = split(b);
+    (p, ((a_hi * b_hi - p) + a_hi * b_lo + a_lo * b_hi) + a_lo * b_lo)
+}
+
+/// An unevaluated sum of two doubles, good for about 32 significant digits.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+impl Real for DoubleDouble {
+    fn from_f64(value: f64) -> Self {
+        Self { hi: value, lo: 0.0 }
+    }
+
+    fn from_expansion(parts: [f64; 4]) -> Self {
+        let (hi, lo) = quick_two_sum(parts[0], parts[1] + parts[2]);
+        Self { hi, lo }
+    }
+
+    fn to_f64(self) -> f64 {
+        self.hi
+    }
+}
+
+impl Add for DoubleDouble {
+    type Output = Self;
+
+    fn add(self, b: Self) -> Self {
+        let (s1, s2) = two_sum(self.hi, b.hi);
+        let (t1, t2) = two_sum(self.lo, b.lo);
+        let (s1, s2) = quick_two_sum(s1, s2 + t1);
+        let (hi, lo) = quick_two_sum(s1, s2 + t2);
+        Self { hi, lo }
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self { hi: -self.hi, lo: -self.lo }
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = Self;
+
+    fn sub(self, b: Self) -> Self {
+        self + -b
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = Self;
+
+    fn mul(self, b: Self) -> Self {
+        let (p1, p2) = two_prod(self.hi, b.hi);
+        let (hi, lo) = quick_two_sum(p1, p2 + (self.hi * b.lo + self.lo * b.hi));
+        Self { hi, lo }
+    }
+}
+
+/// An unevaluated sum of four doubles, good for about 64 significant digits.
+///
+/// The arithmetic follows the "sloppy" algorithms of the QD library by
+/// Hida, Li and Bailey.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct QuadDouble([f64; 4]);
+
+impl Real for QuadDouble {
+    fn from_f64(value: f64) -> Self {
+        Self([value, 0.0, 0.0, 0.0])
+    }
+
+    fn from_expansion(parts: [f64; 4]) -> Self {
+        Self(renorm(parts[0], parts[1], parts[2], parts[3], 0.0))
+    }
+
+    fn to_f64(self) -> f64 {
+        self.0[0]
+    }
+}
+
+/// Add a, b and c in place, leaving the sum in a and the errors in b and c.
+fn three_sum(a: &mut f64, b: &mut f64, c: &mut f64) {
+    let (t1, t2) = two_sum(*a, *b);
+    let (s, t3) = two_sum(*c, t1);
+    *a = s;
+    (*b, *c) = two_sum(t2, t3);
+}
+
+/// three_sum when only the first two terms of the result are needed.
+fn three_sum2(a: &mut f64, b: &mut f64, c: f64) {
+    let (t1, t2) = two_sum(*a, *b);
+    let (s, t3) = two_sum(c, t1);
+    *a = s;
+    *b = t2 + t3;
+}
+
+/// Normalize five overlapping terms into four that do not overlap.
+fn renorm(c0: f64, c1: f64, c2: f64, c3: f64, c4: f64) -> [f64; 4] {
+    if c0.is_infinite() {
+        return [c0, c1, c2, c3];
+    }
+    let (s0, c4) = quick_two_sum(c3, c4);
+    let (s0, c3) = quick_two_sum(c2, s0);
+    let (s0, c2) = quick_two_sum(c1, s0);
+    let (c0, c1) = quick_two_sum(c0, s0);
+
+    let (mut s0, mut s1, mut s2, mut s3) = (c0, c1, 0.0, 0.0);
+    if s1 != 0.0 {
+        (s1, s2) = quick_two_sum(s1, c2);
+        if s2 != 0.0 {
+            (s2, s3) = quick_two_sum(s2, c3);
+            if s3 != 0.0 {
+                s3 += c4;
+            } else {
+                (s2, s3) = quick_two_sum(s2, c4);
+            }
+        } else {
+            (s1, s2) = quick_two_sum(s1, c3);
+            if s2 != 0.0 {
+                (s2, s3) = quick_two_sum(s2, c4);
+            } else {
+                (s1, s2) = quick_two_sum(s1, c4);
+            }
+        }
+    } else {
+        (s0, s1) = quick_two_sum(s0, c2);
+        if s1 != 0.0 {
+            (s1, s2) = quick_two_sum(s1, c3);
+            if s2 != 0.0 {
+                (s2, s3) = quick_two_sum(s2, c4);
+            } else {
+                (s1, s2) = quick_two_sum(s1, c4);
+            }
+        } else {
+            (s0, s1) = quick_two_sum(s0, c3);
+            if s1 != 0.0 {
+                (s1, s2) = quick_two_sum(s1, c4);
+            } else {
+                (s0, s1) = quick_two_sum(s0, c4);
+            }
+        }
+    }
+    [s0, s1, s2, s3]
+}
+
+impl Add for QuadDouble {
+    type Output = Self;
+
+    fn add(self, b: Self) -> Self {
+        let (a, b) = (self.0, b.0);
+        let (s0, mut t0) = two_sum(a[0], b[0]);
+        let (s1, mut t1) = two_sum(a[1], b[1]);
+        let (mut s2, t2) = two_sum(a[2], b[2]);
+        let (mut s3, t3) = two_sum(a[3], b[3]);
+
+        let (s1, t) = two_sum(s1, t0);
+        t0 = t;
+        three_sum(&mut s2, &mut t0, &mut t1);
+        three_sum2(&mut s3, &mut t0, t2);
+        t0 = t0 + t1 + t3;
+        Self(renorm(s0, s1, s2, s3, t0))
+    }
+}
+
+impl Neg for QuadDouble {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(self.0.map(|x| -x))
+    }
+}
+
+impl Sub for QuadDouble {
+    type Output = Self;
+
+    fn sub(self, b: Self) -> Self {
+        self + -b
+    }
+}
+
+impl Mul for QuadDouble {
+    type Output = Self;
+
+    fn mul(self, b: Self) -> Self {
+        let (a, b) = (self.0, b.0);
+        let (p0, mut q0) = two_prod(a[0], b[0]);
+        let (mut p1, mut q1) = two_prod(a[0], b[1]);
+        let (mut p2, mut q2) = two_prod(a[1], b[0]);
+        let (mut p3, q3) = two_prod(a[0], b[2]);
+        let (mut p4, q4) = two_prod(a[1], b[1]);
+        let (mut p5, q5) = two_prod(a[2], b[0]);
+
+        three_sum(&mut p1, &mut p2, &mut q0);
+        three_sum(&mut p2, &mut q1, &mut q2);
+        three_sum(&mut p3, &mut p4, &mut p5);
+        // (s0, s1, s2) = (p2, q1, q2) + (p3, p4, p5)
+        let (s0, t0) = two_sum(p2, p3);
+        let (s1, t1) = two_sum(q1, p4);
+        let s2 = q2 + p5;
+        let (s1, t0) = two_sum(s1, t0);
+        let s2 = s2 + (t0 + t1);
+        // the terms of order eps^3
+        let s1 = s1 + (a[0] * b[3] + a[1] * b[2] + a[2] * b[1] + a[3] * b[0] + q0 + q3 + q4 + q5);
+        Self(renorm(p0, p1, s0, s1, s2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use bigdecimal::{BigDecimal, FromPrimitive};
+    use crate::bigcoord::{expansion, to_f64};
+    use super::*;
+
+    /// x * 2^shift as an integer, which must be exact.
+    fn scaled(x: f64, shift: i32) -> i128 {
+        let value = x * 2f64.powi(shift);
+        assert_eq!(value.fract(), 0.0, "{} is not a multiple of 2^-{}", x, shift);
+        value as i128
+    }
+
+    fn pow2(exponent: i32) -> f64 {
+        2f64.powi(exponent)
+    }
+
+    /// The exact value of an unevaluated sum of f64s.
+    fn exact(parts: &[f64]) -> BigDecimal {
+        parts.iter().map(|&part| BigDecimal::from_f64(part).unwrap()).sum()
+    }
+
+    fn relative_error(value: &BigDecimal, expected: &BigDecimal) -> f64 {
+        to_f64(&((value - expected).abs() / expected.abs()))
+    }
+
+    /// Numbers with more digits than a QuadDouble holds, as expansions.
+    fn long_operands() -> Vec<[f64; 4]> {
+        [
+            "1.2345678901234567890123456789012345678901234567890123456789012345678901",
+            "-0.7436438870371587522065817761434720126918706349058418590055883478283219",
+            "3.1415926535897932384626433832795028841971693993751058209749445923078164",
+            "0.0001234567898765432123456789876543212345678987654321234567898765432123",
+        ]
+        .iter()
+        .map(|s| expansion(&BigDecimal::from_str(s).unwrap()))
+        .collect()
+    }
+
+    fn quad_from_double(x: DoubleDouble) -> QuadDouble {
+        QuadDouble::from_f64(x.hi) + QuadDouble::from_f64(x.lo)
+    }
+
+    /// x with all of its 106 bits set, far from the simple values above.
+    fn double_double(x: f64) -> DoubleDouble {
+        let (hi, lo) = two_sum(x, x * pow2(-54) / 3.0);
+        DoubleDouble { hi, lo }
+    }
+
+    #[test]
+    fn two_sum_and_two_prod_are_exact() {
+        let pairs = [(1.0, pow2(-60)), (pow2(40), 1.0 + pow2(-12)), (1234.5678, -0.000123), (1.0 / 3.0, 2.0 / 3.0)];
+        for (a, b) in pairs {
+            let (s, e) = two_sum(a, b);
+            assert_eq!(scaled(s, 80) + scaled(e, 80), scaled(a, 80) + scaled(b, 80), "{} + {}", a, b);
+        }
+        // with the scale of each factor, which add up to the scale of the product
+        let pairs = [(1.0 + pow2(-30), 30, 1.0 + pow2(-30), 30), (123456789.0123, 30, -987.654321, 45), (1.0 / 3.0, 60, 3.0, 0)];
+        for (a, a_shift, b, b_shift) in pairs {
+            let (p, e) = two_prod(a, b);
+            let shift = a_shift + b_shift;
+            assert_eq!(scaled(p, shift) + scaled(e, shift), scaled(a, a_shift) * scaled(b, b_shift), "{} * {}", a, b);
+        }
+    }
+
+    #[test]
+    fn renorm_keeps_the_sum_without_overlap() {
+        let terms = [1.0, 3.0 * pow2(-20), pow2(-70), -pow2(-100), pow2(-120)];
+        let parts = renorm(terms[0], terms[1], terms[2], terms[3], terms[4]);
+        let sum = |parts: &[f64]| parts.iter().map(|&p| scaled(p, 120)).sum::<i128>();
+        assert_eq!(sum(&parts), sum(&terms));
+        for pair in parts.windows(2) {
+            assert_eq!(pair[0] + pair[1], pair[0], "{:?}", parts);
+        }
+    }
+
+    #[test]
+    fn double_double_arithmetic_is_within_its_error_bound() {
+        let operands = [double_double(1.0 / 3.0), double_double(-2.0f64.sqrt()), double_double(7.123e-5)];
+        for &x in &operands {
+            for &y in &operands {
+                let (qx, qy) = (quad_from_double(x), quad_from_double(y));
+                let error = |result: DoubleDouble, exact: QuadDouble| {
+                    ((quad_from_double(result) - exact).to_f64() / exact.to_f64()).abs()
+                };
+                assert!(error(x * y, qx * qy) < 1e-30);
+                assert!(error(x * x, qx * qx) < 1e-30);
+                // sums that cancel lose relative precision in any format
+                let sum = quad_from_double(x + y) - (qx + qy);
+                assert!((sum.to_f64() / x.hi.abs().max(y.hi.abs())).abs() < 1e-31);
+            }
+        }
+    }
+
+    #[test]
+    fn quad_double_arithmetic_is_exact_where_the_result_fits() {
+        let quad = |parts: [f64; 4]| QuadDouble(renorm(parts[0], parts[1], parts[2], parts[3], 0.0));
+        let a = quad([1.0, pow2(-60), 0.0, 0.0]);
+        let b = quad([1.0, pow2(-70), 0.0, 0.0]);
+        assert_eq!((a * b).0, [1.0, pow2(-60) + pow2(-70), pow2(-130), 0.0]);
+        assert_eq!((a * a).0, [1.0, pow2(-59), pow2(-120), 0.0]);
+        let c = quad([1.0, pow2(-100), pow2(-200), 0.0]);
+        let d = quad([-1.0, pow2(-150), 0.0, 0.0]);
+        assert_eq!((c + d).0, [pow2(-100) + pow2(-150), pow2(-200), 0.0, 0.0]);
+        assert_eq!((c - c).0, [0.0; 4]);
+    }
+
+    #[test]
+    fn quad_double_arithmetic_is_within_its_error_bound() {
+        let third = quad_from_double(double_double(1.0 / 3.0));
+        let root = quad_from_double(double_double(2.0f64.sqrt()));
+        let (x, y, z) = (third, root, third * root + root);
+        let error = |a: QuadDouble, b: QuadDouble| ((a - b).to_f64() / a.to_f64()).abs();
+        assert!(error((x * y) * z, x * (y * z)) < 1e-62);
+        assert!(error((x + y) - y, x) < 1e-62);
+        assert!(error(z * z, z * (z + x) - z * x) < 1e-61);
+    }
+
+    #[test]
+    fn from_expansion_keeps_the_precision_of_the_type() {
+        for parts in long_operands() {
+            let expected = exact(&parts);
+            let dd = DoubleDouble::from_expansion(parts);
+            assert!(relative_error(&exact(&[dd.hi, dd.lo]), &expected) < 1e-31);
+            let qd = QuadDouble::from_expansion(parts);
+            assert!(relative_error(&exact(&qd.0), &expected) < 1e-62);
+        }
+    }
+
+    #[test]
+    fn quad_double_arithmetic_matches_exact_decimals() {
+        let operands = long_operands();
+        for a in &operands {
+            for b in &operands {
+                let (x, y) = (QuadDouble::from_expansion(*a), QuadDouble::from_expansion(*b));
+                let (ex, ey) = (exact(&x.0), exact(&y.0));
+                let scale = ex.abs().max(ey.abs());
+                assert!(to_f64(&((exact(&(x + y).0) - (&ex + &ey)).abs() / &scale)) < 1e-62);
+                assert!(relative_error(&exact(&(x * y).0), &(&ex * &ey)) < 1e-61);
+                assert!(relative_error(&exact(&(x * x).0), &(&ex * &ex)) < 1e-61);
+            }
+        }
+    }
+
+    #[test]
+    fn big_decimals_survive_a_round_trip() {
+        let value = BigDecimal::from_str("-1.74995768370609350360221450607069970727110579726252077930242837820286").unwrap();
+        let qd = QuadDouble::from_expansion(expansion(&value));
+        assert!(relative_error(&exact(&qd.0), &value) < 1e-62);
+        let dd = DoubleDouble::from_expansion(expansion(&value));
+        assert!(relative_error(&exact(&[dd.hi, dd.lo]), &value) < 1e-31);
+        assert_eq!(qd.to_f64(), to_f64(&value));
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How far a long job has got, updated from any thread while another reads
+/// it. Jobs count whatever units suit them, such as rows or tiles.
+pub struct Progress {
+    done: AtomicU64,
+    total: AtomicU64,
+    started: Mutex<Instant>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self {
+            done: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            started: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Start counting towards total from zero.
+    pub fn start(&self, total: u64) {
+        *self.started.lock().unwrap() = Instant::now();
+        self.done.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, n: u64) {
+        self.done.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, done: u64) {
+        self.done.store(done, Ordering::Relaxed);
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    /// The time since the count was started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.lock().unwrap().elapsed()
+    }
+
+    /// The part done, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        match self.total() {
+            0 => 0.0,
+            total => (self.done() as f64 / total as f64).min(1.0) as f32,
+        }
+    }
+
+    /// The time left if the rest goes as fast as the part done so far, None
+    /// until there is something to go by.
+    pub fn eta(&self) -> Option<Duration> {
+        let (done, total) = (self.done(), self.total());
+        if done == 0 {
+            return None;
+        }
+        Some(self.elapsed().mul_f64(total.saturating_sub(done) as f64 / done as f64))
+    }
+
+    /// `done/total`, with the time left once it is known.
+    pub fn describe(&self) -> String {
+        match self.eta() {
+            Some(eta) => format!("{}/{}, {} left", self.done(), self.total(), format_duration(eta)),
+            None => format!("{}/{}", self.done(), self.total()),
+        }
+    }
+}
+
+/// A duration to the second, such as `1h 02m`, `3m 05s` or `12s`.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64().round() as u64;
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_left_goes_by_the_rate_so_far() {
+        let progress = Progress::new();
+        progress.start(100);
+        assert_eq!((progress.eta(), progress.fraction()), (None, 0.0));
+        *progress.started.lock().unwrap() -= Duration::from_secs(10);
+        progress.advance(25);
+        let eta = progress.eta().unwrap().as_secs_f64();
+        assert!((30.0..30.5).contains(&eta), "{}", eta);
+        assert_eq!(progress.fraction(), 0.25);
+        progress.set(120);
+        assert_eq!((progress.eta(), progress.fraction()), (Some(Duration::ZERO), 1.0));
+    }
+
+    #[test]
+    fn durations_are_shown_to_the_second() {
+        let format = |seconds| format_duration(Duration::from_secs_f64(seconds));
+        assert_eq!(format(12.4), "12s");
+        assert_eq!(format(185.0), "3m 05s");
+        assert_eq!(format(3720.0), "1h 02m");
+    }
+}
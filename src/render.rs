@@ -0,0 +1,56 @@
+use eframe::egui::{Color32, ColorImage};
+use crate::mandelbrot;
+use crate::mandelbrot::{compute_mandelbrot, Sample};
+use crate::palette;
+
+/// The color of a single sample of a render with max_iterations iterations.
+pub fn color_sample<S: Sample>(sample: &S, max_iterations: u32, pal: &palette::Palette) -> Color32 {
+    let escape = sample.escape();
+    if escape >= max_iterations {
+        return Color32::BLACK;
+    }
+    let len = pal.palette.len();
+    match pal.color_mode {
+        palette::ColorMode::LinearScale => {
+            let scale_factor = (len - 1) as f64 / max_iterations as f64;
+            let val = (escape as f64 * scale_factor) as usize;
+            pal.palette[(val + pal.offset) % len]
+        }
+        palette::ColorMode::Modulus => pal.palette[(escape as usize + pal.offset) % len],
+    }
+}
+
+pub fn render_image_to_surface<S: Sample>(
+    fractal: &mandelbrot::Data<S>,
+    image: &mut ColorImage,
+    pal: &palette::Palette,
+) {
+    let max_iterations = fractal.state.max_iterations;
+    for (pixel, entry) in image.pixels.iter_mut().zip(fractal.samples()) {
+        *pixel = color_sample(entry, max_iterations, pal);
+    }
+}
+
+/// Color the rectangle [x, y, width, height] of fractal, such as a tile that
+/// has just been computed.
+pub fn render_region(fractal: &mandelbrot::Data, rect: [usize; 4], pal: &palette::Palette) -> ColorImage {
+    let [x, y, width, height] = rect;
+    let mut state = fractal.state.clone();
+    (state.width, state.height) = (width as u32, height as u32);
+    let mut region = mandelbrot::Data::new(state);
+    for (i, row) in fractal.rows().skip(y).take(height).enumerate() {
+        region.row_mut(i).copy_from_slice(&row[x..x + width]);
+    }
+    let mut image = ColorImage::new([width, height], Color32::BLACK);
+    render_image_to_surface(&region, &mut image, pal);
+    image
+}
+
+/// Compute and color a fractal in one go.
+pub fn render_to_image(state: mandelbrot::State, pal: &palette::Palette) -> ColorImage {
+    let mut image = ColorImage::new([state.width as usize, state.height as usize], Color32::BLACK);
+    let mut fractal: mandelbrot::Data = mandelbrot::Data::new(state);
+    compute_mandelbrot(&mut fractal);
+    render_image_to_surface(&fractal, &mut image, pal);
+    image
+}
@@ -0,0 +1,224 @@
+use crate::mandelbrot::{Complex64, FractalSample};
+
+/// The number of points iterated together in f64, and in f32.
+pub const LANES: usize = 4;
+pub const F32_LANES: usize = 8;
+
+/// The constant and starting value of z of a point, in f32.
+pub type PointF32 = ((f32, f32), (f32, f32));
+
+/// Whether the CPU supports the vectorized kernel.
+pub fn available() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+/// Iterate every point of a row, LANES at a time. point gives the constant
+/// and the starting value of z for each pixel.
+///
+/// The arithmetic is done in the same order as the scalar kernel, without
+/// fused multiply-adds, so the escape counts and final values of z match it
+/// exactly.
+pub fn iterate_row(point: impl Fn(u32) -> (Complex64, Complex64), max_iterations: u32, row: &mut [FractalSample]) {
+    assert!(available(), "the vectorized kernel is not supported on this CPU");
+    for (i, chunk) in row.chunks_mut(LANES).enumerate() {
+        let first = (i * LANES) as u32;
+        // lanes past the end of the row repeat the last point and are thrown
+        // away
+        let points: [(Complex64, Complex64); LANES] =
+            std::array::from_fn(|lane| point(first + lane.min(chunk.len() - 1) as u32));
+        let samples = iterate(points, max_iterations);
+        chunk.copy_from_slice(&samples[..chunk.len()]);
+    }
+}
+
+/// iterate_row in f32. The escape counts are kept as f32, so they are exact
+/// up to 2^24 iterations.
+pub fn iterate_row_f32(point: impl Fn(u32) -> PointF32, max_iterations: u32, row: &mut [FractalSample]) {
+    assert!(available(), "the vectorized kernel is not supported on this CPU");
+    for (i, chunk) in row.chunks_mut(F32_LANES).enumerate() {
+        let first = (i * F32_LANES) as u32;
+        let points: [PointF32; F32_LANES] =
+            std::array::from_fn(|lane| point(first + lane.min(chunk.len() - 1) as u32));
+        let samples = iterate_f32(points, max_iterations);
+        chunk.copy_from_slice(&samples[..chunk.len()]);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn iterate_f32(points: [PointF32; F32_LANES], max_iterations: u32) -> [FractalSample; F32_LANES] {
+    // SAFETY: iterate_row_f32 checked the CPU supports AVX
+    unsafe { avx::iterate_f32(points, max_iterations) }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn iterate_f32(_points: [PointF32; F32_LANES], _max_iterations: u32) -> [FractalSample; F32_LANES] {
+    unreachable!("the vectorized kernel is only available on x86_64")
+}
+
+#[cfg(target_arch = "x86_64")]
+fn iterate(points: [(Complex64, Complex64); LANES], max_iterations: u32) -> [FractalSample; LANES] {
+    // SAFETY: iterate_row checked the CPU supports AVX
+    unsafe { avx::iterate(points, max_iterations) }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn iterate(_points: [(Complex64, Complex64); LANES], _max_iterations: u32) -> [FractalSample; LANES] {
+    unreachable!("the vectorized kernel is only available on x86_64")
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx {
+    use std::arch::x86_64::*;
+    use super::{PointF32, F32_LANES, LANES};
+    use crate::mandelbrot::{Complex64, FractalSample};
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn iterate(points: [(Complex64, Complex64); LANES], max_iterations: u32) -> [FractalSample; LANES] {
+        let c_re = _mm256_setr_pd(points[0].0.re, points[1].0.re, points[2].0.re, points[3].0.re);
+        let c_im = _mm256_setr_pd(points[0].0.im, points[1].0.im, points[2].0.im, points[3].0.im);
+        let mut z_re = _mm256_setr_pd(points[0].1.re, points[1].1.re, points[2].1.re, points[3].1.re);
+        let mut z_im = _mm256_setr_pd(points[0].1.im, points[1].1.im, points[2].1.im, points[3].1.im);
+        let four = _mm256_set1_pd(4.0);
+        let one = _mm256_set1_pd(1.0);
+        // escape counts, kept as doubles so they can be masked like z
+        let mut count = _mm256_setzero_pd();
+        let mut active = _mm256_cmp_pd::<_CMP_LT_OQ>(norm_sqr(z_re, z_im), four);
+        let mut i = 0;
+        while i < max_iterations && _mm256_movemask_pd(active) != 0 {
+            // z * z + c, in the order num_complex does it
+            let re = _mm256_add_pd(_mm256_sub_pd(_mm256_mul_pd(z_re, z_re), _mm256_mul_pd(z_im, z_im)), c_re);
+            let im = _mm256_add_pd(_mm256_add_pd(_mm256_mul_pd(z_re, z_im), _mm256_mul_pd(z_im, z_re)), c_im);
+            // escaped lanes keep the z they escaped with
+            z_re = _mm256_blendv_pd(z_re, re, active);
+            z_im = _mm256_blendv_pd(z_im, im, active);
+            count = _mm256_add_pd(count, _mm256_and_pd(active, one));
+            active = _mm256_and_pd(active, _mm256_cmp_pd::<_CMP_LT_OQ>(norm_sqr(z_re, z_im), four));
+            i += 1;
+        }
+        let mut re = [0.0; LANES];
+        let mut im = [0.0; LANES];
+        let mut escape = [0.0; LANES];
+        _mm256_storeu_pd(re.as_mut_ptr(), z_re);
+        _mm256_storeu_pd(im.as_mut_ptr(), z_im);
+        _mm256_storeu_pd(escape.as_mut_ptr(), count);
+        std::array::from_fn(|lane| FractalSample {
+            z: Complex64::new(re[lane], im[lane]),
+            escape: escape[lane] as u32,
+        })
+    }
+
+    #[target_feature(enable = "avx")]
+    pub unsafe fn iterate_f32(points: [PointF32; F32_LANES], max_iterations: u32) -> [FractalSample; F32_LANES] {
+        let lanes = |f: fn(&PointF32) -> f32| {
+            let v: [f32; F32_LANES] = std::array::from_fn(|lane| f(&points[lane]));
+            v
+        };
+        let c_re = _mm256_loadu_ps(lanes(|p| p.0 .0).as_ptr());
+        let c_im = _mm256_loadu_ps(lanes(|p| p.0 .1).as_ptr());
+        let mut z_re = _mm256_loadu_ps(lanes(|p| p.1 .0).as_ptr());
+        let mut z_im = _mm256_loadu_ps(lanes(|p| p.1 .1).as_ptr());
+        let four = _mm256_set1_ps(4.0);
+        let one = _mm256_set1_ps(1.0);
+        let mut count = _mm256_setzero_ps();
+        let mut active = _mm256_cmp_ps::<_CMP_LT_OQ>(norm_sqr_f32(z_re, z_im), four);
+        let mut i = 0;
+        while i < max_iterations && _mm256_movemask_ps(active) != 0 {
+            let re = _mm256_add_ps(_mm256_sub_ps(_mm256_mul_ps(z_re, z_re), _mm256_mul_ps(z_im, z_im)), c_re);
+            let im = _mm256_add_ps(_mm256_add_ps(_mm256_mul_ps(z_re, z_im), _mm256_mul_ps(z_im, z_re)), c_im);
+            z_re = _mm256_blendv_ps(z_re, re, active);
+            z_im = _mm256_blendv_ps(z_im, im, active);
+            count = _mm256_add_ps(count, _mm256_and_ps(active, one));
+            active = _mm256_and_ps(active, _mm256_cmp_ps::<_CMP_LT_OQ>(norm_sqr_f32(z_re, z_im), four));
+            i += 1;
+        }
+        let mut re = [0.0; F32_LANES];
+        let mut im = [0.0; F32_LANES];
+        let mut escape = [0.0; F32_LANES];
+        _mm256_storeu_ps(re.as_mut_ptr(), z_re);
+        _mm256_storeu_ps(im.as_mut_ptr(), z_im);
+        _mm256_storeu_ps(escape.as_mut_ptr(), count);
+        std::array::from_fn(|lane| FractalSample {
+            z: Complex64::new(re[lane] as f64, im[lane] as f64),
+            escape: escape[lane] as u32,
+        })
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn norm_sqr_f32(re: __m256, im: __m256) -> __m256 {
+        _mm256_add_ps(_mm256_mul_ps(re, re), _mm256_mul_ps(im, im))
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn norm_sqr(re: __m256d, im: __m256d) -> __m256d {
+        _mm256_add_pd(_mm256_mul_pd(re, re), _mm256_mul_pd(im, im))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bigcoord::{self, BigComplex};
+    use crate::mandelbrot::{compute_mandelbrot_with, Complex64, Data, FractalType, Kernel, Precision, State};
+
+    /// Views exercising the vectorized kernels: deep and rotated ones, a
+    /// Julia set, and odd widths that leave a partly filled group of lanes at
+    /// the end of rows.
+    fn views() -> Vec<(&'static str, State)> {
+        let mut base = State::new(160, 120);
+        base.precision = Precision::F64;
+        let mut views = vec![("home", base.clone())];
+        let mut seahorse = base.clone();
+        seahorse.center = BigComplex::parse("-0.743643887, 0.131825904").unwrap();
+        seahorse.scale = bigcoord::from_f64(1e-4);
+        seahorse.max_iterations = 2000;
+        views.push(("seahorse", seahorse.clone()));
+        let mut rotated = seahorse;
+        rotated.rotation = 1.0;
+        views.push(("rotated", rotated));
+        let mut deep = base.clone();
+        deep.center = BigComplex::parse("-1.7499576837060, 0.0000000000001").unwrap();
+        deep.scale = bigcoord::from_f64(1e-11);
+        deep.max_iterations = 2000;
+        views.push(("deep", deep));
+        let mut julia = base.clone();
+        julia.fractal_type = FractalType::Julia(Complex64::new(-0.8, 0.156));
+        views.push(("julia", julia.home()));
+        let mut odd = base.clone();
+        odd.width = 133;
+        views.push(("odd width", odd));
+        let mut preview = base;
+        preview.precision = Precision::F32;
+        preview.max_iterations = 150;
+        views.push(("f32", preview.clone()));
+        preview.width = 133;
+        views.push(("f32 odd width", preview));
+        views
+    }
+
+    #[test]
+    fn vector_kernels_match_the_scalar_ones() {
+        if !super::available() {
+            println!("skipped, the CPU does not support AVX");
+            return;
+        }
+        for (name, state) in views() {
+            let mut scalar: Data = Data::new(state.clone());
+            compute_mandelbrot_with(&mut scalar, Kernel::Scalar);
+            let mut vector: Data = Data::new(state);
+            compute_mandelbrot_with(&mut vector, Kernel::Vector);
+            for (a, b) in scalar.samples().iter().zip(vector.samples()) {
+                assert_eq!(a.escape, b.escape, "{}", name);
+                assert_eq!(a.z.re.to_bits(), b.z.re.to_bits(), "{}", name);
+                assert_eq!(a.z.im.to_bits(), b.z.im.to_bits(), "{}", name);
+            }
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::fmt::Display;
+use std::ops::Range;
+use eframe::egui::{ColorImage, Rgba};
+use rayon::prelude::*;
+use crate::mandelbrot::{compute_mandelbrot, compute_points, Data, EscapeSample, Sample, State};
+use crate::palette::Palette;
+use crate::progress::Progress;
+use crate::render::color_sample;
+
+/// The largest number of samples per pixel along each axis.
+pub const MAX_FACTOR: u32 = 8;
+/// Neighbouring pixels whose escape counts differ by more than this lie on an
+/// edge, and are supersampled in adaptive mode.
+const EDGE_THRESHOLD: u32 = 1;
+
+/// Where the samples within a pixel are placed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// The middles of an evenly spaced grid.
+    Grid,
+    /// A random point within each cell of the grid, which turns the moiré
+    /// of regular patterns into noise.
+    Jitter,
+}
+
+/// How many samples are taken per pixel, with the colors of the samples
+/// averaged into the color of the pixel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Supersampling {
+    pub pattern: Pattern,
+    /// The number of samples along each side of a pixel, 1 is no
+    /// supersampling.
+    pub factor: u32,
+    /// Only supersample pixels on edges, where neighbouring escape counts
+    /// differ, which is most of the improvement for a fraction of the cost.
+    pub adaptive: bool,
+}
+
+impl Default for Supersampling {
+    fn default() -> Self {
+        Self::OFF
+    }
+}
+
+impl Supersampling {
+    pub const OFF: Supersampling = Supersampling {
+        pattern: Pattern::Grid,
+        factor: 1,
+        adaptive: false,
+    };
+
+    pub fn is_off(&self) -> bool {
+        self.factor <= 1
+    }
+
+    /// Parse `off`, `N` for an NxN grid, `jitter:N`, or either of them after
+    /// `adaptive:`, such as `adaptive:jitter:3`.
+    pub fn parse(value: &str) -> Option<Supersampling> {
+        if value == "off" {
+            return Some(Self::OFF);
+        }
+        let (adaptive, rest) = match value.strip_prefix("adaptive:") {
+            Some(rest) => (true, rest),
+            None => (false, value),
+        };
+        let (pattern, factor) = match rest.strip_prefix("jitter:") {
+            Some(factor) => (Pattern::Jitter, factor),
+            None => (Pattern::Grid, rest),
+        };
+        let factor = factor.parse().ok().filter(|f| (1..=MAX_FACTOR).contains(f))?;
+        Some(Supersampling {
+            pattern,
+            factor,
+            adaptive,
+        })
+    }
+
+    /// The samples of pixel (x, y) of the whole image, in pixels from its
+    /// middle.
+    fn positions(&self, x: usize, y: usize) -> Vec<(f64, f64)> {
+        let n = self.factor;
+        let cell = 1.0 / n as f64;
+        (0..n * n)
+            .map(|i| {
+                let (jitter_x, jitter_y) = match self.pattern {
+                    Pattern::Grid => (0.5, 0.5),
+                    Pattern::Jitter => jitter(x, y, i),
+                };
+                let (cx, cy) = ((i % n) as f64, (i / n) as f64);
+                ((cx + jitter_x) * cell - 0.5, (cy + jitter_y) * cell - 0.5)
+            })
+            .collect()
+    }
+}
+
+impl Display for Supersampling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_off() {
+            return write!(f, "off");
+        }
+        if self.adaptive {
+            write!(f, "adaptive:")?;
+        }
+        match self.pattern {
+            Pattern::Grid => write!(f, "{}", self.factor),
+            Pattern::Jitter => write!(f, "jitter:{}", self.factor),
+        }
+    }
+}
+
+/// Two numbers in [0, 1) that look random but are the same for every render
+/// of sample i of pixel (x, y), so renders and their tiles are repeatable.
+fn jitter(x: usize, y: usize, i: u32) -> (f64, f64) {
+    let mut hash = (x as u64).wrapping_mul(0x9e3779b97f4a7c15) ^ (y as u64).wrapping_mul(0xc2b2ae3d27d4eb4f) ^ i as u64;
+    // the finalizer of splitmix64
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^= hash >> 31;
+    let unit = |bits: u64| (bits & 0xffffffff) as f64 / 4294967296.0;
+    (unit(hash), unit(hash >> 32))
+}
+
+/// Whether two neighbouring samples are far enough apart in escape count to
+/// alias. Only the integer counts are compared, so every kind of sample
+/// finds the same edges.
+fn differs<S: Sample>(a: &S, b: &S, max_iterations: u32) -> bool {
+    let (inside_a, inside_b) = (a.escape() >= max_iterations, b.escape() >= max_iterations);
+    inside_a != inside_b || (!inside_a && a.escape().abs_diff(b.escape()) > EDGE_THRESHOLD)
+}
+
+/// Whether pixel (x, y) differs from any of its four neighbours.
+fn on_edge<S: Sample>(fractal: &Data<S>, x: usize, y: usize) -> bool {
+    let (width, height) = (fractal.state.width as usize, fractal.state.height as usize);
+    let sample = fractal.sample(x, y);
+    let max_iterations = fractal.state.max_iterations;
+    let neighbours = [
+        (x.wrapping_sub(1), y),
+        (x + 1, y),
+        (x, y.wrapping_sub(1)),
+        (x, y + 1),
+    ];
+    neighbours
+        .iter()
+        .filter(|&&(nx, ny)| nx < width && ny < height)
+        .any(|&(nx, ny)| differs(&sample, &fractal.sample(nx, ny), max_iterations))
+}
+
+/// Compute and color a fractal in one go, with supersampling.
+pub fn render_state(state: State, supersampling: &Supersampling, pal: &Palette) -> ColorImage {
+    let mut fractal: Data = Data::new(state);
+    compute_mandelbrot(&mut fractal);
+    render(&fractal, supersampling, pal)
+}
+
+/// Color fractal with supersampling, computing the extra samples of each
+/// pixel that needs them and averaging their colors in linear light.
+pub fn render<S: Sample>(fractal: &Data<S>, supersampling: &Supersampling, pal: &Palette) -> ColorImage {
+    render_with_progress(fractal, supersampling, pal, &Progress::new())
+}
+
+/// render, counting the rows supersampled in progress.
+pub fn render_with_progress<S: Sample>(
+    fractal: &Data<S>,
+    supersampling: &Supersampling,
+    pal: &Palette,
+    progress: &Progress,
+) -> ColorImage {
+    render_rows(fractal, 0..fractal.state.height as usize, 0, supersampling, pal, progress)
+}
+
+/// Color rows of fractal with supersampling, where fractal is a band that
+/// starts first_row rows into a larger image. The rows around them are only
+/// there to find the edges, so a band of an image looks exactly like the
+/// same rows of the whole image.
+pub fn render_band<S: Sample>(
+    fractal: &Data<S>,
+    rows: Range<usize>,
+    first_row: usize,
+    supersampling: &Supersampling,
+    pal: &Palette,
+) -> ColorImage {
+    render_rows(fractal, rows, first_row, supersampling, pal, &Progress::new())
+}
+
+/// The extra samples of the supersampled pixels of a render, kept so it can
+/// be recolored without computing them again. Coloring only looks at escape
+/// counts, so that is all that is kept of them.
+pub struct Supersamples {
+    pub state: State,
+    pub supersampling: Supersampling,
+    /// For each row, its supersampled pixels followed by all their samples
+    /// in the same order.
+    rows: Vec<(Vec<usize>, Vec<EscapeSample>)>,
+}
+
+/// Compute the extra samples of the pixels of fractal that need them,
+/// counting the rows done in progress.
+pub fn supersample<S: Sample>(fractal: &Data<S>, supersampling: &Supersampling, progress: &Progress) -> Supersamples {
+    sample_rows(fractal, 0..fractal.state.height as usize, 0, supersampling, progress)
+}
+
+/// Color fractal, averaging the colors of the extra samples of each
+/// supersampled pixel in linear light.
+pub fn color<S: Sample>(fractal: &Data<S>, extra: &Supersamples, pal: &Palette) -> ColorImage {
+    color_rows(fractal, 0..fractal.state.height as usize, extra, pal)
+}
+
+fn render_rows<S: Sample>(
+    fractal: &Data<S>,
+    rows: Range<usize>,
+    first_row: usize,
+    supersampling: &Supersampling,
+    pal: &Palette,
+    progress: &Progress,
+) -> ColorImage {
+    let extra = sample_rows(fractal, rows.clone(), first_row, supersampling, progress);
+    color_rows(fractal, rows, &extra, pal)
+}
+
+fn sample_rows<S: Sample>(
+    fractal: &Data<S>,
+    rows: Range<usize>,
+    first_row: usize,
+    supersampling: &Supersampling,
+    progress: &Progress,
+) -> Supersamples {
+    let state = &fractal.state;
+    let mut extra = Supersamples {
+        state: state.clone(),
+        supersampling: *supersampling,
+        rows: Vec::new(),
+    };
+    if supersampling.is_off() {
+        return extra;
+    }
+    let width = state.width as usize;
+    progress.start(rows.len() as u64);
+    extra.rows = rows
+        .into_par_iter()
+        .map(|y| {
+            let pixels: Vec<usize> = (0..width)
+                .filter(|&x| !supersampling.adaptive || on_edge(fractal, x, y))
+                .collect();
+            // every sample of the row is computed in one go, so the
+            // vectorized kernel is kept busy
+            let offsets: Vec<_> = pixels
+                .iter()
+                .flat_map(|&x| {
+                    supersampling
+                        .positions(x, first_row + y)
+                        .into_iter()
+                        .map(move |(dx, dy)| state.sample_offset(x as f64 + dx, y as f64 + dy))
+                })
+                .collect();
+            let samples = compute_points(state, &offsets).into_iter().map(EscapeSample::from_sample).collect();
+            progress.advance(1);
+            (pixels, samples)
+        })
+        .collect();
+    extra
+}
+
+fn color_rows<S: Sample>(fractal: &Data<S>, rows: Range<usize>, extra: &Supersamples, pal: &Palette) -> ColorImage {
+    let state = &fractal.state;
+    let width = state.width as usize;
+    let mut image = ColorImage::new([width, rows.len()], Default::default());
+    let samples = fractal.rows().skip(rows.start).take(rows.len()).flatten();
+    for (pixel, sample) in image.pixels.iter_mut().zip(samples) {
+        *pixel = color_sample(sample, state.max_iterations, pal);
+    }
+    let samples_per_pixel = (extra.supersampling.factor * extra.supersampling.factor) as usize;
+    image.pixels.par_chunks_mut(width).zip(extra.rows.par_iter()).for_each(|(row, (pixels, samples))| {
+        for (&x, samples) in pixels.iter().zip(samples.chunks(samples_per_pixel)) {
+            let sum = samples
+                .iter()
+                .fold(Rgba::TRANSPARENT, |sum, s| sum + Rgba::from(color_sample(s, state.max_iterations, pal)));
+            row[x] = (sum * (1.0 / samples_per_pixel as f32)).into();
+        }
+    });
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::palette::PaletteType;
+    use super::*;
+
+    #[test]
+    fn settings_are_parsed_and_displayed() {
+        let parsed = |value| Supersampling::parse(value).unwrap();
+        assert_eq!(parsed("off"), Supersampling::OFF);
+        assert_eq!(parsed("3"), Supersampling { pattern: Pattern::Grid, factor: 3, adaptive: false });
+        assert_eq!(parsed("jitter:4"), Supersampling { pattern: Pattern::Jitter, factor: 4, adaptive: false });
+        assert_eq!(parsed("adaptive:jitter:2"), Supersampling { pattern: Pattern::Jitter, factor: 2, adaptive: true });
+        for value in ["off", "3", "jitter:8", "adaptive:2", "adaptive:jitter:5"] {
+            assert_eq!(parsed(value).to_string(), value);
+        }
+        for value in ["", "0", "9", "jitter", "jitter:x", "adaptive:", "grid:2", "jitter:adaptive:2"] {
+            assert_eq!(Supersampling::parse(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn samples_lie_within_their_cells() {
+        let jitter = Supersampling::parse("jitter:4").unwrap();
+        let positions = jitter.positions(17, 1000);
+        assert_eq!(positions, jitter.positions(17, 1000));
+        for (i, &(dx, dy)) in positions.iter().enumerate() {
+            let (cx, cy) = ((i % 4) as f64 / 4.0 - 0.5, (i / 4) as f64 / 4.0 - 0.5);
+            assert!((cx..cx + 0.25).contains(&dx) && (cy..cy + 0.25).contains(&dy), "{} {} {}", i, dx, dy);
+        }
+        let grid = Supersampling::parse("2").unwrap();
+        assert_eq!(grid.positions(0, 0), [(-0.25, -0.25), (0.25, -0.25), (-0.25, 0.25), (0.25, 0.25)]);
+    }
+
+    #[test]
+    fn edges_do_not_depend_on_the_kind_of_sample() {
+        let mut state = State::new(96, 64).home();
+        state.max_iterations = 200;
+        let mut full: Data = Data::new(state.clone());
+        let mut escapes: Data<EscapeSample> = Data::new(state);
+        compute_mandelbrot(&mut full);
+        compute_mandelbrot(&mut escapes);
+        let mut edges = 0;
+        for y in 0..64 {
+            for x in 0..96 {
+                assert_eq!(on_edge(&full, x, y), on_edge(&escapes, x, y), "({}, {})", x, y);
+                edges += on_edge(&full, x, y) as u32;
+            }
+        }
+        assert!(edges > 100, "{}", edges);
+    }
+
+    #[test]
+    fn kept_samples_recolor_like_a_new_render() {
+        let mut fractal: Data = Data::new(State::new(48, 32).home());
+        compute_mandelbrot(&mut fractal);
+        for supersampling in ["off", "2", "adaptive:jitter:3"] {
+            let supersampling = Supersampling::parse(supersampling).unwrap();
+            let extra = supersample(&fractal, &supersampling, &Progress::new());
+            for pal in [PaletteType::BW.to_palette(), PaletteType::Color1Mod.to_palette_with_offset(40)] {
+                assert!(color(&fractal, &extra, &pal).pixels == render(&fractal, &supersampling, &pal).pixels);
+            }
+        }
+    }
+}
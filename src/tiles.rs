@@ -0,0 +1,321 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::f64::consts::LOG10_2;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use bigdecimal::BigDecimal;
+use rayon::prelude::*;
+use crate::bigcoord::{self, BigComplex};
+use crate::diskcache::DiskCache;
+use crate::mandelbrot::{compute_mandelbrot, Complex64, Data, FractalType, Precision, Sampling, State};
+use crate::progress::Progress;
+
+/// The width and height of a tile in pixels.
+pub const TILE_SIZE: u32 = 64;
+/// The number of pixel sizes tiles are rendered at per halving of the scale.
+/// Views are snapped to the nearest, which changes their zoom by at most
+/// about 1%.
+const LEVELS_PER_OCTAVE: f64 = 32.0;
+/// The number of tiles kept in memory, about 100MB.
+const CACHE_TILES: usize = 1024;
+/// Views further than this many pixels from the anchor of every lattice get
+/// a new lattice, as offsets that large start to lose precision in f64.
+const MAX_ANCHOR_DISTANCE: f64 = 1e9;
+
+/// A grid of tiles sharing everything but their position. Pixel (x, y) of
+/// the grid lies x pixels right and y pixels down from the anchor.
+#[derive(Clone, PartialEq)]
+struct Lattice {
+    fractal_type: FractalType,
+    max_iterations: u32,
+    precision: Precision,
+    rotation: f64,
+    level: i64,
+    anchor: BigComplex,
+}
+
+impl Lattice {
+    fn same_grid(&self, other: &Lattice) -> bool {
+        self.fractal_type == other.fractal_type
+            && self.max_iterations == other.max_iterations
+            && self.precision == other.precision
+            && self.rotation == other.rotation
+            && self.level == other.level
+    }
+
+    fn pixel_size(&self) -> BigDecimal {
+        bigcoord::pow10(-(self.level as f64) / LEVELS_PER_OCTAVE * LOG10_2)
+    }
+
+    /// The point at pixel (x, y) of the grid.
+    fn point(&self, x: f64, y: f64, pixel: f64) -> BigComplex {
+        let offset = Complex64::new(x * pixel, -y * pixel) * Complex64::from_polar(1.0, self.rotation);
+        self.anchor.offset(offset, bigcoord::places(pixel))
+    }
+}
+
+/// The address of a tile: its lattice and its column and row in it.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct TileKey {
+    lattice: usize,
+    x: i64,
+    y: i64,
+}
+
+struct CachedTile {
+    data: Arc<Data>,
+    last_used: u64,
+}
+
+/// The most recently used tiles, and the lattices they belong to.
+pub struct TileCache {
+    /// Lattices by id, ids are never reused so keys of dropped lattices
+    /// cannot match new ones.
+    lattices: HashMap<usize, Lattice>,
+    next_lattice: usize,
+    tiles: HashMap<TileKey, CachedTile>,
+    /// The keys of tiles by when they were last used, oldest first.
+    by_use: BTreeMap<u64, TileKey>,
+    clock: u64,
+}
+
+/// A view snapped onto a lattice, so it can be put together from tiles.
+pub struct TileView {
+    /// The view the tiles make up, which is close to the one asked for.
+    pub state: State,
+    lattice_id: usize,
+    lattice: Lattice,
+    /// The pixel of the lattice at the top left corner of the view.
+    origin: (i64, i64),
+}
+
+impl TileCache {
+    pub fn new() -> Self {
+        Self {
+            lattices: HashMap::new(),
+            next_lattice: 0,
+            tiles: HashMap::new(),
+            by_use: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Snap state onto the pixels of a lattice. Log polar views have no
+    /// lattice and give None.
+    pub fn plan(&mut self, state: &State) -> Option<TileView> {
+        if state.sampling != Sampling::Rectangular {
+            return None;
+        }
+        let pixel_log = bigcoord::log10(&state.scale) - (state.width as f64).log10();
+        let mut lattice = Lattice {
+            fractal_type: state.fractal_type.clone(),
+            max_iterations: state.max_iterations,
+            precision: state.precision,
+            rotation: state.rotation,
+            level: (-pixel_log / LOG10_2 * LEVELS_PER_OCTAVE).round() as i64,
+            anchor: state.center.clone(),
+        };
+        let pixel_size = lattice.pixel_size();
+        let pixel = bigcoord::to_f64(&pixel_size);
+        // states are held above MIN_SCALE, so this only catches views
+        // built by hand
+        if pixel == 0.0 {
+            return None;
+        }
+        // lattices whose tiles have all been evicted have nothing to offer
+        let used: HashSet<usize> = self.tiles.keys().map(|key| key.lattice).collect();
+        self.lattices.retain(|id, _| used.contains(id));
+        // the center in pixels from the anchor of an existing lattice, the
+        // oldest if there are several
+        let unrotate = Complex64::from_polar(1.0, -state.rotation);
+        let found = self
+            .lattices
+            .iter()
+            .filter_map(|(&id, l)| {
+                if !l.same_grid(&lattice) {
+                    return None;
+                }
+                let offset = state.center.difference(&l.anchor) * unrotate / pixel;
+                (offset.norm() < MAX_ANCHOR_DISTANCE).then_some((id, offset))
+            })
+            .min_by_key(|&(id, _)| id);
+        let (lattice_id, center) = match found {
+            Some((id, offset)) => {
+                lattice = self.lattices[&id].clone();
+                (id, (offset.re.round() as i64, (-offset.im).round() as i64))
+            }
+            None => {
+                let id = self.next_lattice;
+                self.next_lattice += 1;
+                self.lattices.insert(id, lattice.clone());
+                (id, (0, 0))
+            }
+        };
+        let mut snapped = state.clone();
+        snapped.scale = &pixel_size * BigDecimal::from(state.width);
+        snapped.center = lattice.point(center.0 as f64, center.1 as f64, pixel);
+        let origin = (center.0 - (state.width / 2) as i64, center.1 - (state.height / 2) as i64);
+        Some(TileView {
+            state: snapped,
+            lattice_id,
+            lattice,
+            origin,
+        })
+    }
+
+    fn get(&mut self, key: &TileKey) -> Option<Arc<Data>> {
+        self.clock += 1;
+        let tile = self.tiles.get_mut(key)?;
+        self.by_use.remove(&tile.last_used);
+        self.by_use.insert(self.clock, *key);
+        tile.last_used = self.clock;
+        Some(tile.data.clone())
+    }
+
+    fn insert(&mut self, key: TileKey, data: Arc<Data>) {
+        self.clock += 1;
+        if let Some(old) = self.tiles.insert(key, CachedTile { data, last_used: self.clock }) {
+            self.by_use.remove(&old.last_used);
+        }
+        self.by_use.insert(self.clock, key);
+        if self.tiles.len() > CACHE_TILES {
+            if let Some((_, oldest)) = self.by_use.pop_first() {
+                self.tiles.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl TileView {
+    /// The tiles covering the view, the ones nearest the middle first so
+    /// the part being looked at appears soonest.
+    fn keys(&self) -> Vec<TileKey> {
+        let tile = TILE_SIZE as i64;
+        let (x0, y0) = (self.origin.0.div_euclid(tile), self.origin.1.div_euclid(tile));
+        let x1 = (self.origin.0 + self.state.width as i64 - 1).div_euclid(tile);
+        let y1 = (self.origin.1 + self.state.height as i64 - 1).div_euclid(tile);
+        let mut keys: Vec<TileKey> = (y0..=y1)
+            .flat_map(|y| (x0..=x1).map(move |x| (x, y)))
+            .map(|(x, y)| TileKey { lattice: self.lattice_id, x, y })
+            .collect();
+        let (mx, my) = ((x0 + x1) as f64 / 2.0, (y0 + y1) as f64 / 2.0);
+        keys.sort_by(|a, b| {
+            let distance = |k: &TileKey| (k.x as f64 - mx).powi(2) + (k.y as f64 - my).powi(2);
+            distance(a).total_cmp(&distance(b))
+        });
+        keys
+    }
+
+    /// The render of a single tile.
+    fn tile_state(&self, key: &TileKey) -> State {
+        let mut state = self.state.clone();
+        let size = TILE_SIZE as i64;
+        let pixel_size = self.lattice.pixel_size();
+        let pixel = bigcoord::to_f64(&pixel_size);
+        (state.width, state.height) = (TILE_SIZE, TILE_SIZE);
+        state.scale = pixel_size * BigDecimal::from(TILE_SIZE);
+        let (x, y) = (key.x * size + size / 2, key.y * size + size / 2);
+        state.center = self.lattice.point(x as f64, y as f64, pixel);
+        state
+    }
+
+    /// Copy the part of tile inside the view into data, returning the
+    /// rectangle of the view it covers as [x, y, width, height].
+    fn copy_tile(&self, key: &TileKey, tile: &Data, data: &mut Data) -> [usize; 4] {
+        let size = TILE_SIZE as i64;
+        let (left, top) = (key.x * size - self.origin.0, key.y * size - self.origin.1);
+        let x0 = left.max(0);
+        let x1 = (left + size).min(self.state.width as i64);
+        let y0 = top.max(0);
+        let y1 = (top + size).min(self.state.height as i64);
+        for y in y0..y1 {
+            let row = tile.row((y - top) as usize);
+            data.row_mut(y as usize)[x0 as usize..x1 as usize]
+                .copy_from_slice(&row[(x0 - left) as usize..(x1 - left) as usize]);
+        }
+        [x0 as usize, y0 as usize, (x1 - x0) as usize, (y1 - y0) as usize]
+    }
+}
+
+/// Put the view together from cached tiles, loading the missing ones from
+/// disk or computing them in parallel. on_tile is called with the data so
+/// far and the rectangle of each tile as it is placed, cached tiles first.
+/// progress counts the tiles that were not in memory.
+pub fn render(
+    cache: &mut TileCache,
+    disk: Option<&DiskCache>,
+    view: &TileView,
+    progress: &Progress,
+    on_tile: &mut dyn FnMut(&Data, [usize; 4]),
+) -> Data {
+    let mut data = Data::new(view.state.clone());
+    let mut missing = Vec::new();
+    for key in view.keys() {
+        match cache.get(&key) {
+            Some(tile) => {
+                let rect = view.copy_tile(&key, &tile, &mut data);
+                on_tile(&data, rect);
+            }
+            None => missing.push(key),
+        }
+    }
+    progress.start(missing.len() as u64);
+    if missing.is_empty() {
+        return data;
+    }
+    let (send, recv) = channel();
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            missing.par_iter().for_each_with(send, |send, key| {
+                let tile = match disk {
+                    Some(disk) => disk.compute(view.tile_state(key)),
+                    None => {
+                        let mut tile = Data::new(view.tile_state(key));
+                        compute_mandelbrot(&mut tile);
+                        tile
+                    }
+                };
+                send.send((*key, tile)).ok();
+            });
+        });
+        for (key, tile) in recv {
+            let rect = view.copy_tile(&key, &tile, &mut data);
+            cache.insert(key, Arc::new(tile));
+            progress.advance(1);
+            on_tile(&data, rect);
+        }
+    });
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lattices_without_tiles_are_dropped() {
+        let mut cache = TileCache::new();
+        for i in 0..100 {
+            let mut state = State::new(64, 64);
+            state.scale = bigcoord::from_f64(1e-12);
+            state.center = BigComplex::parse(&format!("{}, 0", i as f64 * 0.001)).unwrap();
+            cache.plan(&state).unwrap();
+        }
+        assert_eq!(cache.lattices.len(), 1);
+    }
+
+    #[test]
+    fn the_least_recently_used_tile_is_evicted() {
+        let mut cache = TileCache::new();
+        let data = Arc::new(Data::new(State::new(1, 1)));
+        let key = |x| TileKey { lattice: 0, x, y: 0 };
+        for x in 0..CACHE_TILES as i64 {
+            cache.insert(key(x), data.clone());
+        }
+        assert!(cache.get(&key(0)).is_some());
+        cache.insert(key(1), data.clone());
+        cache.insert(key(-1), data.clone());
+        assert_eq!((cache.tiles.len(), cache.by_use.len()), (CACHE_TILES, CACHE_TILES));
+        assert!(cache.get(&key(0)).is_some() && cache.get(&key(1)).is_some());
+        assert!(cache.get(&key(2)).is_none());
+    }
+}